@@ -0,0 +1,157 @@
+// ======================== Animation ========================
+
+use std::{
+    io::{Cursor, Write},
+    time::{Duration, Instant},
+};
+
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyModifiers},
+    terminal,
+};
+use image::{
+    codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder},
+    AnimationDecoder, Frame, ImageFormat, RgbaImage,
+};
+
+use termimgview::{decode_image, image_format, Error, Result};
+
+/// Frames shorter than this are shown for `DEFAULT_DELAY`, like browsers do
+const MIN_DELAY: Duration = Duration::from_millis(20);
+const DEFAULT_DELAY: Duration = Duration::from_millis(100);
+
+/// Decode every frame of an animated GIF, APNG or WebP image read from `path` with its delay.
+/// Still images result in a single frame.
+pub fn load_frames(path: &str, bytes: &[u8]) -> Result<Vec<(RgbaImage, Duration)>> {
+    let format = image_format(path, bytes);
+    let frames = match format {
+        Some(ImageFormat::Gif) => decode_frames(bytes, |r| Ok(GifDecoder::new(r)?.into_frames())),
+        Some(ImageFormat::Png) => decode_frames(bytes, |r| {
+            let decoder = PngDecoder::new(r)?;
+            Ok(decoder.is_apng().then(|| decoder.apng().into_frames()))
+        }),
+        Some(ImageFormat::WebP) => decode_frames(bytes, |r| {
+            let decoder = WebPDecoder::new(r)?;
+            Ok(decoder.has_animation().then(|| decoder.into_frames()))
+        }),
+        _ => None,
+    };
+    Ok(match frames {
+        Some(frames) if !frames.is_empty() => frames
+            .into_iter()
+            .map(|frame| {
+                let delay = Duration::from(frame.delay());
+                let delay = if delay < MIN_DELAY {
+                    DEFAULT_DELAY
+                } else {
+                    delay
+                };
+                (frame.into_buffer(), delay)
+            })
+            .collect(),
+        _ => vec![(decode_image(path, bytes, format)?, Duration::ZERO)],
+    })
+}
+
+fn decode_frames<'a, F: IntoFrames<'a>>(
+    bytes: &'a [u8],
+    decoder: impl FnOnce(Cursor<&'a [u8]>) -> image::ImageResult<F>,
+) -> Option<Vec<Frame>> {
+    decoder(Cursor::new(bytes))
+        .ok()?
+        .frames()?
+        .collect_frames()
+        .ok()
+}
+
+/// Decoders that may or may not hold an animation
+trait IntoFrames<'a> {
+    fn frames(self) -> Option<image::Frames<'a>>;
+}
+
+impl<'a> IntoFrames<'a> for image::Frames<'a> {
+    fn frames(self) -> Option<image::Frames<'a>> {
+        Some(self)
+    }
+}
+
+impl<'a> IntoFrames<'a> for Option<image::Frames<'a>> {
+    fn frames(self) -> Option<image::Frames<'a>> {
+        self
+    }
+}
+
+/// Play frames in place with the given delays, calling `draw` to draw a frame over the previous one.
+/// Plays `loops` times, or forever when `0`, until Ctrl-C, `q` or `Esc` is pressed.
+pub fn play(
+    out: &mut dyn Write,
+    delays: &[Duration],
+    loops: u32,
+    mut draw: impl FnMut(&mut dyn Write, usize) -> std::io::Result<()>,
+) -> Result<()> {
+    // Raw mode lets key presses be read without waiting for a newline, and stops Ctrl-C
+    // from killing the process before the cursor is restored
+    terminal::enable_raw_mode().map_err(Error::Terminal)?;
+    let result =
+        write!(out, "{}", cursor::Hide).and_then(|_| play_frames(out, delays, loops, &mut draw));
+    let restored = write!(out, "{}", cursor::Show).and_then(|_| out.flush());
+    // Leave raw mode even when the terminal could not be written to
+    terminal::disable_raw_mode().map_err(Error::Terminal)?;
+    result?;
+    Ok(restored?)
+}
+
+fn play_frames(
+    out: &mut dyn Write,
+    delays: &[Duration],
+    loops: u32,
+    draw: &mut impl FnMut(&mut dyn Write, usize) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let mut played = 0;
+    loop {
+        for (i, delay) in delays.iter().enumerate() {
+            draw(out, i)?;
+            out.flush()?;
+            if wait_for_quit(*delay)? {
+                return Ok(());
+            }
+        }
+        played += 1;
+        if loops != 0 && played >= loops {
+            return Ok(());
+        }
+    }
+}
+
+/// Write output rendered for cooked mode, returning the carriage on newlines as raw mode does not
+pub fn write_raw(out: &mut dyn Write, output: &[u8]) -> std::io::Result<()> {
+    for line in output.split_inclusive(|b| *b == b'\n') {
+        match line.strip_suffix(b"\n") {
+            Some(line) => {
+                out.write_all(line)?;
+                out.write_all(b"\r\n")?;
+            }
+            None => out.write_all(line)?,
+        }
+    }
+    Ok(())
+}
+
+/// Wait for `delay`, returning early with `true` if the user asked to quit
+fn wait_for_quit(delay: Duration) -> std::io::Result<bool> {
+    let deadline = Instant::now() + delay;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() || !event::poll(remaining)? {
+            return Ok(false);
+        }
+        if let Event::Key(key) = event::read()? {
+            let ctrl_c =
+                key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+            if ctrl_c || key.code == KeyCode::Char('q') || key.code == KeyCode::Esc {
+                return Ok(true);
+            }
+        }
+    }
+}
@@ -0,0 +1,494 @@
+// ======================== ANSI art ========================
+
+use std::{
+    io::Write,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crossterm::style::Color;
+
+use crate::{frame::CellGrid, rendering, ColorMode, Error, Result};
+
+/// Characters of the bytes 0x80 to 0xFF in code page 437, the character set of classic ANSI art
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// Ends the text of an ANSI file, before its SAUCE record
+const EOF: u8 = 0x1A;
+const SAUCE_ID: &[u8] = b"SAUCE00";
+const SAUCE_SIZE: usize = 128;
+
+/// Encode a character in code page 437, if it has a byte there
+pub fn encode_cp437(chr: char) -> Option<u8> {
+    match chr {
+        ' '..='~' => Some(chr as u8),
+        '⌂' => Some(0x7f),
+        _ => CP437_HIGH
+            .iter()
+            .position(|high| *high == chr)
+            .map(|i| 0x80 + i as u8),
+    }
+}
+
+/// Decode a byte of code page 437, keeping control characters
+pub fn decode_cp437(byte: u8) -> char {
+    match byte {
+        0x7f => '⌂',
+        0x80.. => CP437_HIGH[byte as usize - 0x80],
+        _ => byte as char,
+    }
+}
+
+/// The SAUCE record describing an ANSI file, from <https://www.acid.org/info/sauce/sauce.htm>
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sauce {
+    pub title: String,
+    pub author: String,
+    pub group: String,
+    /// The date of creation as `CCYYMMDD`
+    pub date: String,
+    /// Columns of the text
+    pub width: u16,
+    /// Lines of the text
+    pub height: u16,
+    /// Whether blinking selects the bright background colors instead
+    pub ice_colors: bool,
+}
+
+impl Sauce {
+    /// A record for text of `width` by `height` cells created today
+    pub fn new(width: u16, height: u16) -> Self {
+        Self {
+            title: String::new(),
+            author: String::new(),
+            group: String::new(),
+            date: today(),
+            width,
+            height,
+            ice_colors: false,
+        }
+    }
+
+    /// The record at the end of `bytes`, if there is one
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let record = bytes.get(bytes.len().checked_sub(SAUCE_SIZE)?..)?;
+        if !record.starts_with(SAUCE_ID) {
+            return None;
+        }
+        let text = |range: std::ops::Range<usize>| {
+            record[range]
+                .iter()
+                .map(|byte| decode_cp437(*byte))
+                .collect::<String>()
+                .trim_end_matches([' ', '\0'])
+                .to_string()
+        };
+        let number = |at: usize| u16::from_le_bytes([record[at], record[at + 1]]);
+        Some(Self {
+            title: text(7..42),
+            author: text(42..62),
+            group: text(62..82),
+            date: text(82..90),
+            width: number(96),
+            height: number(98),
+            ice_colors: record[105] & 1 != 0,
+        })
+    }
+
+    /// Write the record for an ANSI file with `size` bytes of text, starting with the end of file marker
+    pub fn write(&self, out: &mut dyn Write, size: usize) -> Result<()> {
+        let mut record = Vec::with_capacity(SAUCE_SIZE + 1);
+        record.push(EOF);
+        record.extend_from_slice(SAUCE_ID);
+        for (text, length) in [
+            (&self.title, 35),
+            (&self.author, 20),
+            (&self.group, 20),
+            (&self.date, 8),
+        ] {
+            let mut field: Vec<u8> = text.chars().filter_map(encode_cp437).take(length).collect();
+            field.resize(length, b' ');
+            record.extend(field);
+        }
+        record.extend((size as u32).to_le_bytes());
+        // Character data of the ANSi file type
+        record.extend([1, 1]);
+        record.extend(self.width.to_le_bytes());
+        record.extend(self.height.to_le_bytes());
+        record.extend([0; 4]);
+        // No comments, 8 pixel wide letters and iCE colors when used
+        record.push(0);
+        record.push(0b10 | self.ice_colors as u8);
+        let mut font = b"IBM VGA".to_vec();
+        font.resize(22, 0);
+        record.extend(font);
+        out.write_all(&record)?;
+        Ok(())
+    }
+}
+
+/// Write cells as classic ANSI art in code page 437 with 16 colors and a SAUCE record.
+/// Bright colors are selected with bold and, as iCE colors, with blinking for the background.
+pub fn write_cp437(out: &mut dyn Write, grid: &CellGrid) -> Result<()> {
+    let mut text = Vec::new();
+    let mut ice_colors = false;
+    for row in &grid.rows {
+        let mut current = None;
+        for cell in row {
+            let colors = (cell.fg.map(ansi_16), cell.bg.map(ansi_16));
+            if current != Some(colors) {
+                let mut sgr = vec![0];
+                if let Some(fg) = colors.0 {
+                    sgr.extend(if fg >= 8 {
+                        vec![1, 30 + fg - 8]
+                    } else {
+                        vec![30 + fg]
+                    });
+                }
+                if let Some(bg) = colors.1 {
+                    ice_colors |= bg >= 8;
+                    sgr.extend(if bg >= 8 {
+                        vec![5, 40 + bg - 8]
+                    } else {
+                        vec![40 + bg]
+                    });
+                }
+                let sgr: Vec<String> = sgr.iter().map(|code| code.to_string()).collect();
+                write!(text, "\x1b[{}m", sgr.join(";"))?;
+                current = Some(colors);
+            }
+            text.push(encode_cp437(cell.chr).ok_or_else(|| {
+                Error::InvalidArgument(format!(
+                    "'{}' cannot be written in CP437, use the half or blocks shade method",
+                    cell.chr
+                ))
+            })?);
+        }
+        write!(text, "\x1b[0m\r\n")?;
+    }
+    out.write_all(&text)?;
+    let mut sauce = Sauce::new(
+        grid.width().min(u16::MAX as usize) as u16,
+        grid.height().min(u16::MAX as usize) as u16,
+    );
+    sauce.ice_colors = ice_colors;
+    sauce.write(out, text.len())
+}
+
+/// The index of the closest of the 16 ANSI colors
+fn ansi_16(color: Color) -> u8 {
+    match color {
+        Color::AnsiValue(i) if i < 16 => i,
+        color => match rendering::crossterm_to_rgb(color)
+            .and_then(|rgb| rendering::image_to_crossterm_color(rgb, ColorMode::Ansi16))
+        {
+            Some(Color::AnsiValue(i)) => i,
+            _ => 7,
+        },
+    }
+}
+
+/// Whether the input at `path` is ANSI art rather than an image, by its extension or SAUCE record
+pub fn is_ansi(path: &str, bytes: &[u8]) -> bool {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+    extension.as_deref() == Some("ans") || Sauce::parse(bytes).is_some()
+}
+
+/// Write an ANSI file to a terminal.
+/// Files in UTF-8 without a SAUCE record, as written by `--output file.ans`, are written unchanged.
+/// Classic files in code page 437 are decoded, wrapped at the width of their SAUCE record, and their bold and
+/// blinking bright colors are translated to the bright colors of modern terminals.
+pub fn replay(out: &mut dyn Write, bytes: &[u8]) -> Result<()> {
+    let sauce = Sauce::parse(bytes);
+    // The text ends at the end of file marker, followed by the SAUCE record and its comments
+    let end = bytes
+        .iter()
+        .position(|byte| *byte == EOF)
+        .unwrap_or(bytes.len() - sauce.as_ref().map_or(0, |_| SAUCE_SIZE));
+    let text = &bytes[..end];
+    // Classic files have a SAUCE record or are not valid UTF-8
+    if let Some(text) = std::str::from_utf8(text).ok().filter(|_| sauce.is_none()) {
+        write!(out, "{}", text)?;
+        if !text.ends_with('\n') {
+            writeln!(out)?;
+        }
+        return Ok(());
+    }
+    let width = sauce
+        .as_ref()
+        .map_or(80, |sauce| sauce.width.max(1) as usize);
+    let ice_colors = sauce.as_ref().is_some_and(|sauce| sauce.ice_colors);
+    let mut replay = Replay {
+        out,
+        column: 0,
+        saved_column: 0,
+        width,
+        ice_colors,
+        bold: false,
+        blink: false,
+        fg: None,
+        bg: None,
+    };
+    replay.run(text)
+}
+
+/// The state of a classic ANSI file being replayed
+struct Replay<'a> {
+    out: &'a mut dyn Write,
+    column: usize,
+    /// The column of the cursor position saved with `s`
+    saved_column: usize,
+    width: usize,
+    ice_colors: bool,
+    bold: bool,
+    blink: bool,
+    fg: Option<u8>,
+    bg: Option<u8>,
+}
+
+impl Replay<'_> {
+    fn run(&mut self, text: &[u8]) -> Result<()> {
+        let mut i = 0;
+        while i < text.len() {
+            match text[i] {
+                0x1b if text.get(i + 1) == Some(&b'[') => {
+                    // Parameters and intermediate bytes up to the final byte of the sequence
+                    let start = i + 2;
+                    let Some(length) = text[start..]
+                        .iter()
+                        .position(|byte| (0x40..=0x7e).contains(byte))
+                    else {
+                        break;
+                    };
+                    let parameters = String::from_utf8_lossy(&text[start..start + length]);
+                    self.sequence(&parameters, text[start + length])?;
+                    i = start + length + 1;
+                    continue;
+                }
+                b'\r' => {
+                    self.column = 0;
+                    write!(self.out, "\r")?;
+                }
+                // Terminals return to the first column on line feeds, as do files without `\r`
+                b'\n' => {
+                    self.column = 0;
+                    writeln!(self.out)?;
+                }
+                byte if byte < 0x20 => {}
+                byte => {
+                    if self.column >= self.width {
+                        write!(self.out, "\r\n")?;
+                        self.column = 0;
+                    }
+                    write!(self.out, "{}", decode_cp437(byte))?;
+                    self.column += 1;
+                }
+            }
+            i += 1;
+        }
+        write!(self.out, "\x1b[0m")?;
+        if self.column > 0 {
+            writeln!(self.out)?;
+        }
+        Ok(())
+    }
+
+    /// Apply a control sequence, keeping track of the column and translating colors
+    fn sequence(&mut self, parameters: &str, command: u8) -> Result<()> {
+        let count = parameters.parse::<usize>().unwrap_or(1).max(1);
+        match command {
+            b'm' => return self.select_graphic_rendition(parameters),
+            b'C' => self.column = (self.column + count).min(self.width),
+            b'D' => self.column = self.column.saturating_sub(count),
+            // The cursor position is `row;column`, counted from 1
+            b'H' | b'f' => {
+                let column = parameters.split(';').nth(1);
+                let column = column.and_then(|column| column.parse::<usize>().ok());
+                self.column = (column.unwrap_or(1).max(1) - 1).min(self.width);
+            }
+            b's' => self.saved_column = self.column,
+            b'u' => self.column = self.saved_column,
+            _ => {}
+        }
+        write!(self.out, "\x1b[{}{}", parameters, command as char)?;
+        Ok(())
+    }
+
+    fn select_graphic_rendition(&mut self, parameters: &str) -> Result<()> {
+        for code in parameters.split(';') {
+            match code.parse::<u8>().unwrap_or(0) {
+                0 => {
+                    self.bold = false;
+                    self.blink = false;
+                    self.fg = None;
+                    self.bg = None;
+                }
+                1 => self.bold = true,
+                5 => self.blink = true,
+                22 => self.bold = false,
+                25 => self.blink = false,
+                code @ 30..=37 => self.fg = Some(code - 30),
+                39 => self.fg = None,
+                code @ 40..=47 => self.bg = Some(code - 40),
+                49 => self.bg = None,
+                _ => {}
+            }
+        }
+        let mut sgr = vec![0];
+        // Bold makes the foreground bright, as on the VGA text mode
+        match self.fg {
+            Some(fg) => sgr.push(fg + if self.bold { 90 } else { 30 }),
+            None if self.bold => sgr.push(97),
+            None => {}
+        }
+        if let Some(bg) = self.bg {
+            sgr.push(
+                bg + if self.blink && self.ice_colors {
+                    100
+                } else {
+                    40
+                },
+            );
+        }
+        if self.blink && !self.ice_colors {
+            sgr.push(5);
+        }
+        let sgr: Vec<String> = sgr.iter().map(|code| code.to_string()).collect();
+        write!(self.out, "\x1b[{}m", sgr.join(";"))?;
+        Ok(())
+    }
+}
+
+/// The current date as `CCYYMMDD`
+fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs() / 86400) as i64;
+    date(days)
+}
+
+/// The date `days` after 1970-01-01 as `CCYYMMDD`, by Howard Hinnant's civil date algorithm
+fn date(days: i64) -> String {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    format!("{:04}{:02}{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::Cell;
+
+    /// ANSI art with a SAUCE record of `width` columns
+    fn classic(text: &[u8], width: u16) -> Vec<u8> {
+        let mut bytes = text.to_vec();
+        Sauce::new(width, 1).write(&mut bytes, text.len()).unwrap();
+        bytes
+    }
+
+    fn replayed(bytes: &[u8]) -> String {
+        let mut out = Vec::new();
+        replay(&mut out, bytes).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn sauce_round_trip() {
+        let sauce = Sauce {
+            title: "Café".to_string(),
+            author: "Author".to_string(),
+            group: String::new(),
+            date: "20261019".to_string(),
+            width: 132,
+            height: 300,
+            ice_colors: true,
+        };
+        let text = b"\x1b[0mText\r\n";
+        let mut bytes = text.to_vec();
+        sauce.write(&mut bytes, text.len()).unwrap();
+        assert_eq!(bytes.len(), text.len() + 1 + SAUCE_SIZE);
+        assert_eq!(bytes[text.len()], EOF);
+        assert_eq!(Sauce::parse(&bytes), Some(sauce));
+        let record = &bytes[bytes.len() - SAUCE_SIZE..];
+        assert_eq!(record[90..94], (text.len() as u32).to_le_bytes());
+        assert_eq!(Sauce::parse(text), None);
+    }
+
+    #[test]
+    fn cp437_round_trip() {
+        for byte in 0x20..=0xff {
+            assert_eq!(encode_cp437(decode_cp437(byte)), Some(byte));
+        }
+        assert_eq!(decode_cp437(0xdf), '▀');
+        assert_eq!(encode_cp437('🬀'), None);
+    }
+
+    #[test]
+    fn cp437_bright_colors() {
+        let mut grid = CellGrid::new(ColorMode::Ansi16);
+        grid.push(Cell {
+            chr: '▀',
+            fg: Some(Color::AnsiValue(9)),
+            bg: Some(Color::AnsiValue(12)),
+        });
+        grid.push(Cell {
+            chr: 'A',
+            fg: Some(Color::AnsiValue(2)),
+            bg: None,
+        });
+        grid.end_line();
+        let mut out = Vec::new();
+        write_cp437(&mut out, &grid).unwrap();
+        let text = b"\x1b[0;1;31;5;44m\xdf\x1b[0;32mA\x1b[0m\r\n";
+        assert_eq!(out[..text.len()], text[..]);
+        let sauce = Sauce::parse(&out).unwrap();
+        assert_eq!((sauce.width, sauce.height, sauce.ice_colors), (2, 1, true));
+    }
+
+    #[test]
+    fn replay_classic() {
+        // Bold red, wrapped at the width of 3 columns before the line break
+        assert_eq!(
+            replayed(&classic(b"\x1b[1;31mABCD\xdb\r\nEF", 3)),
+            "\x1b[0;91mABC\r\nD█\r\nEF\x1b[0m\n"
+        );
+        // Line feeds without carriage returns start the next line in the first column
+        assert_eq!(replayed(&classic(b"ABCD\nEFGH", 4)), "ABCD\nEFGH\x1b[0m\n");
+        // Positioned, saved and restored cursors are followed to the column they move to
+        assert_eq!(
+            replayed(&classic(b"AB\x1b[sCD\x1b[uX\x1b[1;4HYZ", 4)),
+            "AB\x1b[sCD\x1b[uX\x1b[1;4HY\r\nZ\x1b[0m\n"
+        );
+        // UTF-8 without a SAUCE record is written unchanged
+        assert_eq!(replayed("▀▄\n".as_bytes()), "▀▄\n");
+    }
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(date(0), "19700101");
+        assert_eq!(date(-1), "19691231");
+        assert_eq!(date(10956), "19991231");
+        assert_eq!(date(11016), "20000229");
+        assert_eq!(date(47541), "21000301");
+    }
+}
@@ -0,0 +1,219 @@
+// ======================== Configuration ========================
+
+use std::{collections::HashMap, ffi::OsString, path::PathBuf};
+
+use clap::{Arg, ArgAction};
+use termimgview::{Error, Result};
+
+/// Tables of the configuration file that are not option defaults
+const PRESETS: &str = "preset";
+const RAMPS: &str = "ramp";
+
+/// The user configuration, with defaults for the command line options, presets and ramps
+#[derive(Debug, Default)]
+pub struct Config {
+    /// Where the configuration was read from
+    path: String,
+    table: toml::Table,
+}
+
+impl Config {
+    /// The configuration file, in `~/.config/termimgview/config.toml` unless `XDG_CONFIG_HOME` is set
+    pub fn path() -> Option<PathBuf> {
+        let config = std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME")
+                    .or_else(|| std::env::var_os("USERPROFILE"))
+                    .map(|home| PathBuf::from(home).join(".config"))
+            })?;
+        Some(config.join(env!("CARGO_PKG_NAME")).join("config.toml"))
+    }
+
+    /// Read the configuration file, which is empty when there is none
+    pub fn load() -> Result<Self> {
+        let Some(path) = Self::path().filter(|path| path.is_file()) else {
+            return Ok(Self::default());
+        };
+        let display = path.to_string_lossy().into_owned();
+        let text = std::fs::read_to_string(&path).map_err(|source| Error::Io {
+            path: Some(display.clone()),
+            source,
+        })?;
+        let table = text.parse::<toml::Table>().map_err(|error| {
+            Error::InvalidArgument(format!("Invalid configuration {}: {}", display, error))
+        })?;
+        Ok(Self {
+            path: display,
+            table,
+        })
+    }
+
+    /// Command line options from the defaults and the preset, which overrides them.
+    /// Keys are long option names of `command`, flags are set with `true`.
+    pub fn arguments(
+        &self,
+        command: &clap::Command,
+        preset: Option<&str>,
+    ) -> Result<Vec<OsString>> {
+        let mut options: Vec<(&String, &toml::Value)> = self
+            .table
+            .iter()
+            .filter(|(key, _)| *key != PRESETS && *key != RAMPS)
+            .collect();
+        if let Some(name) = preset {
+            let preset = self
+                .table
+                .get(PRESETS)
+                .and_then(|presets| presets.get(name))
+                .and_then(|preset| preset.as_table())
+                .ok_or_else(|| Error::InvalidArgument(format!("Unknown preset: {}", name)))?;
+            for (key, value) in preset {
+                options.retain(|(existing, _)| *existing != key);
+                options.push((key, value));
+            }
+        }
+        let mut arguments = Vec::new();
+        for (key, value) in options {
+            let invalid = || {
+                Error::InvalidArgument(format!(
+                    "Invalid configuration {}: {} = {}",
+                    self.path, key, value
+                ))
+            };
+            let arg = command
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(key.as_str()) && key != PRESETS)
+                .filter(|arg| !matches!(arg.get_action(), ArgAction::Help | ArgAction::Version))
+                .ok_or_else(invalid)?;
+            let flag = !arg.get_action().takes_values();
+            let value = match value {
+                toml::Value::Boolean(set) if flag => {
+                    if *set {
+                        arguments.push(format!("--{}", key).into());
+                    }
+                    continue;
+                }
+                _ if flag => return Err(invalid()),
+                toml::Value::String(value) => value.clone(),
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Float(value) => value.to_string(),
+                _ => return Err(invalid()),
+            };
+            // Joined with `=` so values starting with `-` are not taken for options
+            arguments.push(format!("--{}={}", key, value).into());
+        }
+        Ok(arguments)
+    }
+
+    /// Add a hidden `--no-<flag>` for every flag of `command`, which turns off the flag
+    /// when it comes later, so flags set in the configuration can be turned off again
+    pub fn negations(command: clap::Command) -> clap::Command {
+        let flags: Vec<(clap::Id, String)> = command
+            .get_arguments()
+            .filter(|arg| matches!(arg.get_action(), ArgAction::SetTrue))
+            .filter_map(|arg| Some((arg.get_id().clone(), arg.get_long()?.to_string())))
+            .collect();
+        flags.into_iter().fold(command, |command, (id, long)| {
+            let negation = format!("no-{}", long);
+            command
+                .mut_arg(id.clone(), |arg| arg.overrides_with(negation.clone()))
+                .arg(
+                    Arg::new(negation.clone())
+                        .long(negation)
+                        .action(ArgAction::SetTrue)
+                        .overrides_with(id)
+                        .hide(true),
+                )
+        })
+    }
+
+    /// Named character ramps, usable as shade methods
+    pub fn ramps(&self) -> Result<HashMap<String, String>> {
+        let Some(ramps) = self.table.get(RAMPS) else {
+            return Ok(HashMap::new());
+        };
+        let invalid = || Error::InvalidArgument(format!("Invalid ramps in {}", self.path));
+        ramps
+            .as_table()
+            .ok_or_else(invalid)?
+            .iter()
+            .map(|(name, chars)| match chars.as_str() {
+                Some(chars) if !chars.is_empty() => Ok((name.to_lowercase(), chars.to_string())),
+                _ => Err(invalid()),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command() -> clap::Command {
+        clap::Command::new("test")
+            .arg(Arg::new("width").long("width"))
+            .arg(
+                Arg::new("grayscale")
+                    .long("grayscale")
+                    .action(ArgAction::SetTrue),
+            )
+            .arg(Arg::new("invert").long("invert").action(ArgAction::SetTrue))
+    }
+
+    fn config(text: &str) -> Config {
+        Config {
+            path: "config.toml".to_string(),
+            table: text.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn preset_precedence() {
+        let config = config(
+            "width = 10\ngrayscale = true\ninvert = false\n\n[preset.small]\nwidth = 4\ninvert = true\n",
+        );
+        let arguments = |preset| config.arguments(&command(), preset).unwrap();
+        assert_eq!(arguments(None), ["--grayscale", "--width=10"]);
+        assert_eq!(
+            arguments(Some("small")),
+            ["--grayscale", "--invert", "--width=4"]
+        );
+        assert!(config.arguments(&command(), Some("large")).is_err());
+    }
+
+    #[test]
+    fn invalid_values() {
+        for text in [
+            "height = 10",
+            "grayscale = \"yes\"",
+            "grayscale = 1",
+            "width = [10]",
+            "width = true",
+            "help = true",
+        ] {
+            assert!(
+                config(text).arguments(&command(), None).is_err(),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn negated_flags() {
+        let command = Config::negations(command());
+        let grayscale = |arguments: &[&str]| {
+            command
+                .clone()
+                .try_get_matches_from(["test"].iter().chain(arguments))
+                .unwrap()
+                .get_flag("grayscale")
+        };
+        assert!(grayscale(&["--grayscale"]));
+        assert!(!grayscale(&["--grayscale", "--no-grayscale"]));
+        assert!(grayscale(&["--no-grayscale", "--grayscale"]));
+        assert!(!grayscale(&["--invert", "--no-invert"]));
+    }
+}
@@ -0,0 +1,365 @@
+// ======================== Terminal capability detection ========================
+
+use std::{
+    io::{self, IsTerminal},
+    time::{Duration, Instant},
+};
+
+use crate::{ColorMode, Protocol, ShadeMethod};
+
+/// How long to wait for the terminal to answer a query
+pub const PROBE_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Kitty graphics query for a 1x1 RGB image, answered with `ESC _Gi=31;OK ESC \` when supported
+const KITTY_QUERY: &[u8] = b"\x1b_Gi=31,s=1,v=1,a=q,t=d,f=24;AAAA\x1b\\";
+/// Primary device attributes, answered by virtually every terminal
+const DA1_QUERY: &[u8] = b"\x1b[c";
+/// Cell size in pixels, answered with `CSI 6 ; <height> ; <width> t`
+const CELL_SIZE_QUERY: &[u8] = b"\x1b[16t";
+/// Text area size in pixels, answered with `CSI 4 ; <height> ; <width> t`
+const TEXT_AREA_QUERY: &[u8] = b"\x1b[14t";
+
+/// A connection to a terminal that can be queried.
+/// Implemented by the controlling TTY, and by anything replaying canned terminal responses.
+pub trait TerminalIo {
+    fn send(&mut self, query: &[u8]) -> io::Result<()>;
+    /// Read response bytes into `buf`, waiting at most `timeout`. Returns `0` on timeout.
+    fn receive(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize>;
+}
+
+/// Send `query` and collect the response until `done` accepts it or the timeout expires
+pub fn query(
+    tty: &mut dyn TerminalIo,
+    query: &[u8],
+    timeout: Duration,
+    done: impl Fn(&[u8]) -> bool,
+) -> io::Result<Vec<u8>> {
+    tty.send(query)?;
+    let deadline = Instant::now() + timeout;
+    let mut response = Vec::new();
+    let mut buf = [0u8; 256];
+    while !done(&response) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+        match tty.receive(&mut buf, remaining)? {
+            0 => break,
+            n => response.extend_from_slice(&buf[..n]),
+        }
+    }
+    Ok(response)
+}
+
+/// Find the parameters of a CSI sequence `ESC [ <prefix> <params> <terminator>` in a response,
+/// skipping other sequences the terminal sent before it
+pub fn parse_csi(response: &[u8], prefix: &str, terminator: u8) -> Option<Vec<u32>> {
+    let intro = [b"\x1b[".as_slice(), prefix.as_bytes()].concat();
+    (0..response.len().saturating_sub(intro.len() - 1))
+        .filter(|i| response[*i..].starts_with(&intro))
+        .find_map(|i| {
+            let rest = &response[i + intro.len()..];
+            let end = rest
+                .iter()
+                .position(|b| !b.is_ascii_digit() && *b != b';')?;
+            if rest[end] != terminator {
+                return None;
+            }
+            Some(
+                std::str::from_utf8(&rest[..end])
+                    .ok()?
+                    .split(';')
+                    .filter_map(|p| p.parse().ok())
+                    .collect(),
+            )
+        })
+}
+
+/// Graphics and text features supported by the terminal
+#[derive(Debug, Clone, Copy)]
+pub struct Capabilities {
+    pub kitty: bool,
+    pub iterm: bool,
+    pub sixel: bool,
+    pub unicode: bool,
+    pub colors: ColorMode,
+}
+
+impl Capabilities {
+    /// Guess capabilities from environment variables like `TERM`, `TERM_PROGRAM` and `COLORTERM`
+    pub fn from_env(var: impl Fn(&str) -> Option<String>) -> Self {
+        let term = var("TERM").unwrap_or_default().to_lowercase();
+        let program = var("TERM_PROGRAM").unwrap_or_default();
+        let colorterm = var("COLORTERM").unwrap_or_default().to_lowercase();
+        // See https://no-color.org
+        let colors = if var("NO_COLOR").is_some_and(|v| !v.is_empty()) || term == "dumb" {
+            ColorMode::None
+        } else if colorterm == "truecolor" || colorterm == "24bit" || cfg!(windows) {
+            ColorMode::TrueColor
+        } else if term.contains("256color") {
+            ColorMode::Ansi256
+        } else {
+            ColorMode::Ansi16
+        };
+        let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+            .iter()
+            .find_map(|name| var(name).filter(|v| !v.is_empty()))
+            .unwrap_or_default()
+            .to_lowercase();
+        Self {
+            kitty: term.contains("kitty") || var("KITTY_WINDOW_ID").is_some(),
+            iterm: matches!(program.as_str(), "iTerm.app" | "WezTerm" | "vscode"),
+            sixel: term.contains("mlterm") || term.contains("foot"),
+            unicode: term != "dumb"
+                && (cfg!(windows) || locale.contains("utf-8") || locale.contains("utf8")),
+            colors,
+        }
+    }
+
+    /// Add the features reported by the terminal itself to those guessed from the environment
+    pub fn probe(mut self, tty: &mut dyn TerminalIo, timeout: Duration) -> io::Result<Self> {
+        // Terminals without kitty graphics ignore the first query, but all answer DA1
+        let response = query(tty, &[KITTY_QUERY, DA1_QUERY].concat(), timeout, |r| {
+            parse_csi(r, "?", b'c').is_some()
+        })?;
+        if response.windows(10).any(|w| w == b"\x1b_Gi=31;OK") {
+            self.kitty = true;
+        }
+        if let Some(attributes) = parse_csi(&response, "?", b'c') {
+            // Attribute 4 means sixel graphics
+            self.sixel |= attributes.iter().skip(1).any(|a| *a == 4);
+        }
+        Ok(self)
+    }
+
+    /// Detect the capabilities of the terminal attached to stdout
+    pub fn detect() -> Self {
+        let env = Self::from_env(|name| std::env::var(name).ok());
+        if !io::stdout().is_terminal() {
+            return Self {
+                kitty: false,
+                iterm: false,
+                sixel: false,
+                ..env
+            };
+        }
+        with_tty(|tty| env.probe(tty, PROBE_TIMEOUT)).unwrap_or(env)
+    }
+
+    /// The best protocol available, preferring real pixels over character cells
+    pub fn protocol(&self) -> Protocol {
+        if self.kitty {
+            Protocol::Kitty
+        } else if self.iterm {
+            Protocol::Iterm
+        } else if self.sixel {
+            Protocol::Sixel
+        } else {
+            Protocol::Text
+        }
+    }
+
+    /// The best shading method for text output
+    pub fn shading(&self) -> ShadeMethod {
+        if self.unicode {
+            ShadeMethod::Half
+        } else {
+            ShadeMethod::Ascii
+        }
+    }
+}
+
+/// Ask the terminal for the pixel size of a character cell, given the terminal size in cells
+pub fn probe_cell_size(
+    tty: &mut dyn TerminalIo,
+    timeout: Duration,
+    (columns, rows): (u16, u16),
+) -> io::Result<Option<(f32, f32)>> {
+    // DA1 is answered last, so there is no need to wait for queries the terminal ignores
+    let queries = [CELL_SIZE_QUERY, TEXT_AREA_QUERY, DA1_QUERY].concat();
+    let response = query(tty, &queries, timeout, |r| {
+        parse_csi(r, "?", b'c').is_some()
+    })?;
+    let pixels = |prefix| match parse_csi(&response, prefix, b't')?.as_slice() {
+        [height, width] if *height > 0 && *width > 0 => Some((*width as f32, *height as f32)),
+        _ => None,
+    };
+    Ok(pixels("6;").or_else(|| {
+        let (width, height) = pixels("4;")?;
+        (columns > 0 && rows > 0).then(|| (width / columns as f32, height / rows as f32))
+    }))
+}
+
+/// The pixel size of a character cell of the terminal attached to stdout, if it can be found out
+pub fn cell_size() -> Option<(f32, f32)> {
+    if !io::stdout().is_terminal() {
+        return None;
+    }
+    // The kernel knows the pixel size when the terminal reported it, which spares a query
+    if let Ok(size) = crossterm::terminal::window_size() {
+        if size.width > 0 && size.height > 0 && size.columns > 0 && size.rows > 0 {
+            return Some((
+                size.width as f32 / size.columns as f32,
+                size.height as f32 / size.rows as f32,
+            ));
+        }
+    }
+    let cells = crossterm::terminal::size().ok()?;
+    with_tty(|tty| probe_cell_size(tty, PROBE_TIMEOUT, cells))
+        .ok()
+        .flatten()
+}
+
+/// Run `f` with the controlling terminal in raw mode, so responses are neither echoed nor line buffered
+#[cfg(unix)]
+pub fn with_tty<T>(f: impl FnOnce(&mut dyn TerminalIo) -> io::Result<T>) -> io::Result<T> {
+    let mut tty = Tty::open()?;
+    let was_raw = crossterm::terminal::is_raw_mode_enabled()?;
+    crossterm::terminal::enable_raw_mode()?;
+    let result = f(&mut tty);
+    if !was_raw {
+        crossterm::terminal::disable_raw_mode()?;
+    }
+    result
+}
+
+#[cfg(not(unix))]
+pub fn with_tty<T>(_f: impl FnOnce(&mut dyn TerminalIo) -> io::Result<T>) -> io::Result<T> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Terminal queries are not supported on this platform",
+    ))
+}
+
+/// The controlling terminal, `/dev/tty`
+#[cfg(unix)]
+pub struct Tty {
+    file: std::fs::File,
+}
+
+#[cfg(unix)]
+impl Tty {
+    pub fn open() -> io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/tty")?;
+        Ok(Self { file })
+    }
+}
+
+#[cfg(unix)]
+impl TerminalIo for Tty {
+    fn send(&mut self, query: &[u8]) -> io::Result<()> {
+        use std::io::Write;
+        self.file.write_all(query)?;
+        self.file.flush()
+    }
+
+    fn receive(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        use std::{io::Read, os::unix::io::AsRawFd};
+        let mut fd = libc::pollfd {
+            fd: self.file.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = timeout.as_millis().min(i32::MAX as u128) as i32;
+        match unsafe { libc::poll(&mut fd, 1, timeout) } {
+            -1 => Err(io::Error::last_os_error()),
+            0 => Ok(0),
+            _ => self.file.read(buf),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A terminal replaying canned responses, or never answering when there are none
+    struct FakeTerminal {
+        responses: Vec<u8>,
+        sent: Vec<u8>,
+    }
+
+    impl FakeTerminal {
+        fn new(responses: &[u8]) -> Self {
+            Self {
+                responses: responses.to_vec(),
+                sent: Vec::new(),
+            }
+        }
+    }
+
+    impl TerminalIo for FakeTerminal {
+        fn send(&mut self, query: &[u8]) -> io::Result<()> {
+            self.sent.extend_from_slice(query);
+            Ok(())
+        }
+
+        fn receive(&mut self, buf: &mut [u8], _timeout: Duration) -> io::Result<usize> {
+            let n = buf.len().min(self.responses.len());
+            buf[..n].copy_from_slice(&self.responses[..n]);
+            self.responses.drain(..n);
+            Ok(n)
+        }
+    }
+
+    fn probe(responses: &[u8]) -> Capabilities {
+        let env = Capabilities::from_env(|_| None);
+        env.probe(&mut FakeTerminal::new(responses), PROBE_TIMEOUT)
+            .unwrap()
+    }
+
+    #[test]
+    fn kitty_reply() {
+        let capabilities = probe(b"\x1b_Gi=31;OK\x1b\\\x1b[?62;22c");
+        assert!(capabilities.kitty);
+        assert!(!capabilities.sixel);
+        assert_eq!(capabilities.protocol(), Protocol::Kitty);
+    }
+
+    #[test]
+    fn sixel_attribute() {
+        assert!(probe(b"\x1b[?62;4;22c").sixel);
+        assert!(!probe(b"\x1b[?62;22c").sixel);
+        assert_eq!(probe(b"\x1b[?62;4c").protocol(), Protocol::Sixel);
+    }
+
+    #[test]
+    fn interleaved_reply() {
+        // A mode report, which also starts with `ESC [ ?`, before the device attributes
+        let response = b"\x1b[?2026;2$y\x1b[?62;4c";
+        assert_eq!(parse_csi(response, "?", b'c'), Some(vec![62, 4]));
+        assert_eq!(parse_csi(response, "?", b't'), None);
+        assert!(probe(response).sixel);
+    }
+
+    #[test]
+    fn silent_terminal() {
+        let mut tty = FakeTerminal::new(b"");
+        let capabilities = Capabilities::from_env(|name| (name == "TERM").then(|| "foot".into()))
+            .probe(&mut tty, Duration::from_millis(10))
+            .unwrap();
+        assert!(!capabilities.kitty);
+        // Guessed from the environment
+        assert!(capabilities.sixel);
+        assert_eq!(tty.sent, [KITTY_QUERY, DA1_QUERY].concat());
+        let cell_size = probe_cell_size(&mut tty, Duration::from_millis(10), (80, 24)).unwrap();
+        assert_eq!(cell_size, None);
+    }
+
+    #[test]
+    fn cell_size_reply() {
+        let mut tty = FakeTerminal::new(b"\x1b[6;17;8t\x1b[4;408;640t\x1b[?62c");
+        let cell_size = probe_cell_size(&mut tty, PROBE_TIMEOUT, (80, 24)).unwrap();
+        assert_eq!(cell_size, Some((8.0, 17.0)));
+    }
+
+    #[test]
+    fn text_area_fallback() {
+        let mut tty = FakeTerminal::new(b"\x1b[4;408;640t\x1b[?62c");
+        let cell_size = probe_cell_size(&mut tty, PROBE_TIMEOUT, (80, 24)).unwrap();
+        assert_eq!(cell_size, Some((8.0, 17.0)));
+    }
+}
@@ -0,0 +1,100 @@
+// ======================== Errors ========================
+
+use std::{fmt::Display, io};
+
+use crate::STDIN;
+
+/// Everything that can go wrong while viewing an image
+#[derive(Debug)]
+pub enum Error {
+    /// Reading an image or writing the output failed
+    Io {
+        /// The file being read or written, if any
+        path: Option<String>,
+        source: io::Error,
+    },
+    /// The image data is corrupt or truncated
+    Decode {
+        path: String,
+        source: image::ImageError,
+    },
+    /// The image is in a format that cannot be decoded
+    UnsupportedFormat { path: String },
+    /// A command line argument has an invalid value
+    InvalidArgument(String),
+    /// The terminal could not be set up or read from
+    Terminal(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// The process exit code, distinct for every kind of error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::InvalidArgument(_) => 2,
+            Error::Io { .. } => 3,
+            Error::Decode { .. } => 4,
+            Error::UnsupportedFormat { .. } => 5,
+            Error::Terminal(_) => 6,
+        }
+    }
+
+    /// Attach the path of the image to a decoding error
+    pub fn decode(path: &str, source: image::ImageError) -> Self {
+        match source {
+            image::ImageError::Unsupported(_) => Error::UnsupportedFormat {
+                path: path.to_string(),
+            },
+            source => Error::Decode {
+                path: path.to_string(),
+                source,
+            },
+        }
+    }
+}
+
+/// Names stdin for `-`, and quotes paths
+fn describe(path: &str) -> String {
+    if path == STDIN {
+        "stdin".to_string()
+    } else {
+        format!("'{}'", path)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io {
+                path: Some(path),
+                source,
+            } => write!(f, "Failed to access {}: {}", describe(path), source),
+            Error::Io { path: None, source } => write!(f, "Failed to write output: {}", source),
+            Error::Decode { path, source } => {
+                write!(f, "Failed to decode {}: {}", describe(path), source)
+            }
+            Error::UnsupportedFormat { path } => {
+                write!(f, "Unsupported image format of {}", describe(path))
+            }
+            Error::InvalidArgument(message) => write!(f, "{}", message),
+            Error::Terminal(source) => write!(f, "Terminal error: {}", source),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } | Error::Terminal(source) => Some(source),
+            Error::Decode { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(source: io::Error) -> Self {
+        Error::Io { path: None, source }
+    }
+}
@@ -0,0 +1,326 @@
+// ======================== Export ========================
+
+use std::{fmt::Display, io::Write, path::Path};
+
+use crossterm::style::Color;
+
+use crate::{
+    ansi,
+    frame::{Cell, CellGrid},
+    rendering, Result, STDOUT,
+};
+
+/// Fonts of exported pages, which should all have cells of `FONT_WIDTH` em
+pub const FONT_FAMILY: &str = "'DejaVu Sans Mono', Menlo, Consolas, monospace";
+/// Advance width of a character of `FONT_FAMILY` in em
+pub const FONT_WIDTH: f32 = 0.6;
+
+/// File formats cells can be exported to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Html,
+    Svg,
+    /// The escape sequences written to terminals
+    Ansi,
+    /// Classic ANSI art in code page 437 with 16 colors and a SAUCE record
+    Cp437,
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Format::Html => write!(f, "html"),
+            Format::Svg => write!(f, "svg"),
+            Format::Ansi => write!(f, "ansi"),
+            Format::Cp437 => write!(f, "cp437"),
+        }
+    }
+}
+
+impl Format {
+    /// The format of a file by its extension, where stdout gets the escape sequences of terminals
+    pub fn from_path(path: &str) -> Option<Self> {
+        if path == STDOUT {
+            return Some(Format::Ansi);
+        }
+        let extension = Path::new(path).extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "html" | "htm" => Some(Format::Html),
+            "svg" => Some(Format::Svg),
+            "ans" => Some(Format::Ansi),
+            _ => None,
+        }
+    }
+}
+
+/// Write cells in `format`, drawn in cells of `aspect_ratio` width / height
+pub fn write(
+    out: &mut dyn Write,
+    grid: &CellGrid,
+    format: Format,
+    aspect_ratio: f32,
+) -> Result<()> {
+    match format {
+        Format::Html => html(out, grid, aspect_ratio),
+        Format::Svg => svg(out, grid, aspect_ratio),
+        Format::Ansi => Ok(grid.write(out, "\n")?),
+        Format::Cp437 => ansi::write_cp437(out, grid),
+    }
+}
+
+/// Write cells as an HTML page with a `<pre>` of colored runs, its line height keeping the cell aspect ratio
+pub fn html(out: &mut dyn Write, grid: &CellGrid, aspect_ratio: f32) -> Result<()> {
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, "<html>")?;
+    writeln!(out, "<head>")?;
+    writeln!(out, "<meta charset=\"utf-8\">")?;
+    writeln!(out, "<title>{}</title>", env!("CARGO_PKG_NAME"))?;
+    writeln!(out, "</head>")?;
+    writeln!(out, "<body style=\"background:#000;color:#fff\">")?;
+    write!(
+        out,
+        "<pre style=\"font-family:{};font-size:16px;line-height:{:.4}em;margin:0\">",
+        FONT_FAMILY,
+        FONT_WIDTH / aspect_ratio
+    )?;
+    for row in &grid.rows {
+        // Cells of the same colors share a span, like the color runs of terminal output
+        for run in row.chunk_by(|a, b| a.fg == b.fg && a.bg == b.bg) {
+            write_run(out, run)?;
+        }
+        writeln!(out)?;
+    }
+    writeln!(out, "</pre>")?;
+    writeln!(out, "</body>")?;
+    writeln!(out, "</html>")?;
+    Ok(())
+}
+
+/// Write a run of cells with the same colors, in a span unless they have none
+fn write_run(out: &mut dyn Write, run: &[Cell]) -> Result<()> {
+    let text: String = run.iter().map(|cell| escape(cell.chr)).collect();
+    let mut style = Vec::new();
+    if let Some(color) = run[0].fg.and_then(css_color) {
+        style.push(format!("color:{}", color));
+    }
+    if let Some(color) = run[0].bg.and_then(css_color) {
+        style.push(format!("background:{}", color));
+    }
+    if style.is_empty() {
+        write!(out, "{}", text)?;
+    } else {
+        write!(out, "<span style=\"{}\">{}</span>", style.join(";"), text)?;
+    }
+    Ok(())
+}
+
+/// Width of a cell in SVG user units
+const SVG_CELL_WIDTH: f32 = 10.0;
+/// Colors of cells without colors, the same as those of HTML pages
+const DEFAULT_FOREGROUND: &str = "#ffffff";
+const DEFAULT_BACKGROUND: &str = "#000000";
+
+/// Write cells as an SVG image, block characters as rectangles of their exact sub-pixels
+/// and all other characters as text
+pub fn svg(out: &mut dyn Write, grid: &CellGrid, aspect_ratio: f32) -> Result<()> {
+    let (cell_width, cell_height) = (SVG_CELL_WIDTH, SVG_CELL_WIDTH / aspect_ratio);
+    let (width, height) = (
+        grid.width() as f32 * cell_width,
+        grid.height() as f32 * cell_height,
+    );
+    writeln!(
+        out,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">",
+        number(width),
+        number(height)
+    )?;
+    // Without anti-aliasing, the rectangles of neighbouring cells leave no seams
+    writeln!(out, "<g shape-rendering=\"crispEdges\">")?;
+    let background = Some(DEFAULT_BACKGROUND.to_string());
+    write_rect(out, (0.0, 0.0, width, height), &background)?;
+    let mut text = Vec::new();
+    for (y, row) in grid.rows.iter().enumerate() {
+        for (x, cell) in row.iter().enumerate() {
+            let (left, top) = (x as f32 * cell_width, y as f32 * cell_height);
+            match rendering::block_mask(cell.chr) {
+                Some((rows, mask)) => {
+                    // Lit sub-pixels are drawn even without colors, in the default foreground
+                    let foreground = Some(
+                        cell.fg
+                            .and_then(css_color)
+                            .unwrap_or(DEFAULT_FOREGROUND.to_string()),
+                    );
+                    let background = cell.bg.and_then(css_color);
+                    let sub_height = cell_height / rows as f32;
+                    for sub_row in 0..rows {
+                        let lit = (mask >> (sub_row * 2)) & 0b11;
+                        let top = top + sub_row as f32 * sub_height;
+                        let rect = (left, top, cell_width, sub_height);
+                        // Both halves of a sub-row in the same color are a single rectangle
+                        match lit {
+                            0b00 => write_rect(out, rect, &background)?,
+                            0b11 => write_rect(out, rect, &foreground)?,
+                            _ => {
+                                let half = cell_width / 2.0;
+                                let (first, second) = if lit == 0b01 {
+                                    (&foreground, &background)
+                                } else {
+                                    (&background, &foreground)
+                                };
+                                write_rect(out, (left, top, half, sub_height), first)?;
+                                write_rect(out, (left + half, top, half, sub_height), second)?;
+                            }
+                        }
+                    }
+                }
+                None => {
+                    let background = cell.bg.and_then(css_color);
+                    write_rect(out, (left, top, cell_width, cell_height), &background)?;
+                    if !cell.chr.is_whitespace() {
+                        text.push((left + cell_width / 2.0, top + cell_height / 2.0, *cell));
+                    }
+                }
+            }
+        }
+    }
+    writeln!(out, "</g>")?;
+    // Glyphs are drawn over all backgrounds, so that tall glyphs are not cut off by the next row
+    if !text.is_empty() {
+        writeln!(
+            out,
+            "<g font-family=\"{}\" font-size=\"{}\" fill=\"{}\" text-anchor=\"middle\" dominant-baseline=\"central\">",
+            FONT_FAMILY.replace('\'', "&apos;"),
+            number(cell_width / FONT_WIDTH),
+            DEFAULT_FOREGROUND
+        )?;
+        for (x, y, cell) in text {
+            let fill = cell
+                .fg
+                .and_then(css_color)
+                .map(|color| format!(" fill=\"{}\"", color))
+                .unwrap_or_default();
+            writeln!(
+                out,
+                "<text x=\"{}\" y=\"{}\"{}>{}</text>",
+                number(x),
+                number(y),
+                fill,
+                escape(cell.chr)
+            )?;
+        }
+        writeln!(out, "</g>")?;
+    }
+    writeln!(out, "</svg>")?;
+    Ok(())
+}
+
+/// Write a rectangle of `(x, y, width, height)`, nothing without a fill
+fn write_rect(
+    out: &mut dyn Write,
+    (x, y, width, height): (f32, f32, f32, f32),
+    fill: &Option<String>,
+) -> Result<()> {
+    let Some(fill) = fill else {
+        return Ok(());
+    };
+    writeln!(
+        out,
+        "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>",
+        number(x),
+        number(y),
+        number(width),
+        number(height),
+        fill
+    )?;
+    Ok(())
+}
+
+/// A coordinate with at most three decimals
+fn number(value: f32) -> String {
+    let value = format!("{:.3}", value);
+    value
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+/// A terminal color as a CSS hex color
+fn css_color(color: Color) -> Option<String> {
+    let rgb = rendering::crossterm_to_rgb(color)?;
+    Some(format!("#{:02x}{:02x}{:02x}", rgb[0], rgb[1], rgb[2]))
+}
+
+fn escape(chr: char) -> String {
+    match chr {
+        '&' => "&amp;".to_string(),
+        '<' => "&lt;".to_string(),
+        '>' => "&gt;".to_string(),
+        chr => chr.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ColorMode;
+    use image::Rgb;
+
+    #[test]
+    fn html_runs() {
+        let (red, blue) = (Some(Rgb([255, 0, 0])), Some(Rgb([0, 0, 255])));
+        let mut grid = CellGrid::new(ColorMode::TrueColor);
+        grid.add('a', red, None);
+        grid.add('b', red, None);
+        grid.add(' ', None, None);
+        grid.add('<', red, blue);
+        grid.end_line();
+        grid.add('&', None, None);
+        grid.end_line();
+        let mut out = Vec::new();
+        html(&mut out, &grid, 0.5).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "<!DOCTYPE html>\n\
+             <html>\n\
+             <head>\n\
+             <meta charset=\"utf-8\">\n\
+             <title>termimgview</title>\n\
+             </head>\n\
+             <body style=\"background:#000;color:#fff\">\n\
+             <pre style=\"font-family:'DejaVu Sans Mono', Menlo, Consolas, monospace;font-size:16px;line-height:1.2000em;margin:0\">\
+             <span style=\"color:#ff0000\">ab</span> \
+             <span style=\"color:#ff0000;background:#0000ff\">&lt;</span>\n\
+             &amp;\n\
+             </pre>\n\
+             </body>\n\
+             </html>\n"
+        );
+    }
+
+    #[test]
+    fn svg_half_blocks() {
+        let (red, blue) = (Some(Rgb([255, 0, 0])), Some(Rgb([0, 0, 255])));
+        let mut grid = CellGrid::new(ColorMode::TrueColor);
+        grid.add('▀', red, blue);
+        grid.add('▄', red, None);
+        grid.add('x', None, None);
+        grid.end_line();
+        let mut out = Vec::new();
+        svg(&mut out, &grid, 0.5).unwrap();
+        // The upper cell of the lower half block shows the black background
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"30\" height=\"20\" viewBox=\"0 0 30 20\">\n\
+             <g shape-rendering=\"crispEdges\">\n\
+             <rect x=\"0\" y=\"0\" width=\"30\" height=\"20\" fill=\"#000000\"/>\n\
+             <rect x=\"0\" y=\"0\" width=\"10\" height=\"10\" fill=\"#ff0000\"/>\n\
+             <rect x=\"0\" y=\"10\" width=\"10\" height=\"10\" fill=\"#0000ff\"/>\n\
+             <rect x=\"10\" y=\"10\" width=\"10\" height=\"10\" fill=\"#ff0000\"/>\n\
+             </g>\n\
+             <g font-family=\"&apos;DejaVu Sans Mono&apos;, Menlo, Consolas, monospace\" font-size=\"16.667\" fill=\"#ffffff\" text-anchor=\"middle\" dominant-baseline=\"central\">\n\
+             <text x=\"25\" y=\"10\">x</text>\n\
+             </g>\n\
+             </svg>\n"
+        );
+    }
+}
@@ -0,0 +1,266 @@
+// ======================== Filters ========================
+
+use image::{Rgb, RgbaImage};
+
+use crate::{processing, Error, Result};
+
+/// A stage of a pipeline, changing an image in place
+pub trait Filter {
+    fn apply(&self, img: &mut RgbaImage);
+
+    /// Whether the filter makes a color transparent, which takes the place of removing the background
+    fn removes_color(&self) -> bool {
+        false
+    }
+}
+
+/// Keep only the region at `(x, y)` of `width` by `height` pixels, at least a single pixel of the image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Filter for Crop {
+    fn apply(&self, img: &mut RgbaImage) {
+        if img.width() == 0 || img.height() == 0 {
+            return;
+        }
+        let x = self.x.min(img.width().saturating_sub(1));
+        let y = self.y.min(img.height().saturating_sub(1));
+        let width = self.width.clamp(1, img.width() - x);
+        let height = self.height.clamp(1, img.height() - y);
+        *img = image::imageops::crop_imm(img, x, y, width, height).to_image();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Invert;
+
+impl Filter for Invert {
+    fn apply(&self, img: &mut RgbaImage) {
+        processing::invert_img(img);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Grayscale;
+
+impl Filter for Grayscale {
+    fn apply(&self, img: &mut RgbaImage) {
+        processing::grayscale_img(img);
+    }
+}
+
+/// Make pixels within `tolerance` of `color` transparent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RemoveColor {
+    pub color: Rgb<u8>,
+    pub tolerance: f32,
+}
+
+impl Filter for RemoveColor {
+    fn apply(&self, img: &mut RgbaImage) {
+        processing::remove_bg_color(img, self.color, self.tolerance);
+    }
+
+    fn removes_color(&self) -> bool {
+        true
+    }
+}
+
+/// Add a value to every channel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Brightness(pub i32);
+
+impl Filter for Brightness {
+    fn apply(&self, img: &mut RgbaImage) {
+        processing::brightness_img(img, self.0);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Contrast(pub f32);
+
+impl Filter for Contrast {
+    fn apply(&self, img: &mut RgbaImage) {
+        processing::contrast_img(img, self.0);
+    }
+}
+
+/// Rotate the hue by a number of degrees
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HueRotate(pub i32);
+
+impl Filter for HueRotate {
+    fn apply(&self, img: &mut RgbaImage) {
+        processing::hue_rotate_img(img, self.0);
+    }
+}
+
+/// Filters applied one after another, in the order they were added
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn Filter>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a stage after all others
+    pub fn then<F: Filter + 'static>(mut self, filter: F) -> Self {
+        self.push(Box::new(filter));
+        self
+    }
+
+    /// Add a boxed stage after all others, like [`Pipeline::then`]
+    pub fn push(&mut self, stage: Box<dyn Filter>) {
+        self.stages.push(stage);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// Parse stages separated by `|`, like `crop=10,10,200,200|hue=90|contrast=1.4|invert`
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut pipeline = Self::new();
+        if spec.trim().is_empty() {
+            return Ok(pipeline);
+        }
+        for stage in spec.split('|') {
+            pipeline.push(parse_stage(stage.trim())?);
+        }
+        Ok(pipeline)
+    }
+}
+
+impl Filter for Pipeline {
+    fn apply(&self, img: &mut RgbaImage) {
+        for stage in &self.stages {
+            stage.apply(img);
+        }
+    }
+
+    fn removes_color(&self) -> bool {
+        self.stages.iter().any(|stage| stage.removes_color())
+    }
+}
+
+/// Parse a single stage like `hue=90`, where filters without arguments have no `=`
+fn parse_stage(stage: &str) -> Result<Box<dyn Filter>> {
+    let (name, args) = match stage.split_once('=') {
+        Some((name, args)) => (name.trim(), Some(args)),
+        None => (stage, None),
+    };
+    let invalid = || Error::InvalidArgument(format!("Invalid filter stage: {}", stage));
+    // The comma separated arguments of the stage, `count` of them or one less if the last is optional
+    let numbers = |count: usize, optional: bool| -> Result<Vec<f32>> {
+        let values = args
+            .ok_or_else(invalid)?
+            .split(',')
+            .map(|value| value.trim().parse::<f32>().map_err(|_| invalid()))
+            .collect::<Result<Vec<f32>>>()?;
+        if values.len() == count || optional && values.len() == count - 1 {
+            Ok(values)
+        } else {
+            Err(invalid())
+        }
+    };
+    let no_args = |filter: Box<dyn Filter>| match args {
+        None => Ok(filter),
+        Some(_) => Err(invalid()),
+    };
+    Ok(match name.to_lowercase().as_str() {
+        "crop" => {
+            let values = numbers(4, false)?;
+            if values.iter().any(|value| *value < 0.0) {
+                return Err(invalid());
+            }
+            Box::new(Crop {
+                x: values[0] as u32,
+                y: values[1] as u32,
+                width: values[2] as u32,
+                height: values[3] as u32,
+            })
+        }
+        "invert" => no_args(Box::new(Invert))?,
+        "grayscale" => no_args(Box::new(Grayscale))?,
+        "rm-color" => {
+            let values = numbers(4, true)?;
+            if values[..3]
+                .iter()
+                .any(|value| !(0.0..=255.0).contains(value))
+            {
+                return Err(invalid());
+            }
+            Box::new(RemoveColor {
+                color: Rgb([values[0] as u8, values[1] as u8, values[2] as u8]),
+                tolerance: values.get(3).copied().unwrap_or(80.0),
+            })
+        }
+        "brightness" => Box::new(Brightness(numbers(1, false)?[0] as i32)),
+        "contrast" => Box::new(Contrast(numbers(1, false)?[0])),
+        "hue" => Box::new(HueRotate(numbers(1, false)?[0] as i32)),
+        _ => return Err(invalid()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The pixel of a single pixel image after the pipeline
+    fn apply(spec: &str, pixel: [u8; 4]) -> [u8; 4] {
+        let mut img = RgbaImage::from_pixel(1, 1, image::Rgba(pixel));
+        Pipeline::parse(spec).unwrap().apply(&mut img);
+        img.get_pixel(0, 0).0
+    }
+
+    #[test]
+    fn stages_in_order() {
+        assert_eq!(
+            apply("brightness=100|invert", [100, 100, 100, 255]),
+            [55, 55, 55, 255]
+        );
+        assert_eq!(
+            apply(" invert | brightness=100 ", [100, 100, 100, 255]),
+            [255, 255, 255, 255]
+        );
+        assert!(Pipeline::parse("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_stages() {
+        for spec in [
+            "blur=2",
+            "invert=1",
+            "hue",
+            "contrast=high",
+            "crop=0,0,10",
+            "crop=-1,0,10,10",
+            "rm-color=0,0",
+            "rm-color=256,0,0",
+            "rm-color=0,0,0,10,10",
+            "invert||grayscale",
+        ] {
+            assert!(Pipeline::parse(spec).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn rm_color_tolerance() {
+        // 20 away from the removed color
+        let pixel = [30, 10, 10, 255];
+        assert_eq!(apply("rm-color=10,10,10", pixel), [0, 0, 0, 0]);
+        assert_eq!(apply("rm-color=10,10,10,5", pixel), pixel);
+        assert!(Pipeline::parse("rm-color=10,10,10")
+            .unwrap()
+            .removes_color());
+        assert!(!Pipeline::parse("invert|hue=90").unwrap().removes_color());
+    }
+}
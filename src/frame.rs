@@ -0,0 +1,250 @@
+// ======================== Cell frames ========================
+
+use std::{fmt::Display, io::Write};
+
+use crossterm::{
+    cursor,
+    style::{self, Color},
+    terminal::{Clear, ClearType},
+};
+use image::Rgb;
+
+use crate::{rendering::image_to_crossterm_color, ColorMode};
+
+/// Begin and end a synchronized update, so the terminal shows a frame only once it is complete
+const BEGIN_SYNCHRONIZED_UPDATE: &str = "\x1b[?2026h";
+const END_SYNCHRONIZED_UPDATE: &str = "\x1b[?2026l";
+
+/// A character with the terminal colors it is drawn in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cell {
+    pub chr: char,
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+}
+
+/// The character cells produced by a text renderer, row by row
+#[derive(Debug, Clone)]
+pub struct CellGrid {
+    pub colors: ColorMode,
+    pub rows: Vec<Vec<Cell>>,
+    line: Vec<Cell>,
+}
+
+impl CellGrid {
+    pub fn new(colors: ColorMode) -> Self {
+        Self {
+            colors,
+            rows: Vec::new(),
+            line: Vec::new(),
+        }
+    }
+
+    /// Add a cell to the current line, mapping its colors to the color mode
+    pub fn add(&mut self, chr: char, color: Option<Rgb<u8>>, bg_color: Option<Rgb<u8>>) {
+        self.push(Cell {
+            chr,
+            fg: color.and_then(|c| image_to_crossterm_color(c, self.colors)),
+            bg: bg_color.and_then(|c| image_to_crossterm_color(c, self.colors)),
+        });
+    }
+
+    /// Add a cell whose colors are already mapped to the color mode
+    pub fn push(&mut self, cell: Cell) {
+        self.line.push(cell);
+    }
+
+    pub fn end_line(&mut self) {
+        self.rows.push(std::mem::take(&mut self.line));
+    }
+
+    pub fn width(&self) -> usize {
+        self.rows.iter().map(|row| row.len()).max().unwrap_or(0)
+    }
+
+    pub fn height(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Write every row followed by `newline`
+    pub fn write(&self, out: &mut dyn Write, newline: &str) -> Result<(), std::io::Error> {
+        let mut renderer = LineRenderer::new(self.colors);
+        for row in &self.rows {
+            for cell in row {
+                renderer.add(*cell);
+            }
+            write!(out, "{}{}", renderer.build(), newline)?;
+            renderer.clear();
+        }
+        Ok(())
+    }
+}
+
+/// The escape sequence selecting a terminal color. The 16 ANSI colors use the classic
+/// `30-37`/`90-97` codes, as some terminals without 256 colors do not understand `38;5`.
+fn set_color(color: Color, colors: ColorMode, background: bool) -> String {
+    match (color, colors) {
+        (Color::AnsiValue(i), ColorMode::Ansi16) => {
+            let base = if i < 8 { 30 + i } else { 90 + i - 8 };
+            format!("\x1b[{}m", base + if background { 10 } else { 0 })
+        }
+        (color, _) if background => style::SetBackgroundColor(color).to_string(),
+        (color, _) => style::SetForegroundColor(color).to_string(),
+    }
+}
+
+/// Builds a line of cells, only emitting color changes between runs of cells
+struct LineRenderer {
+    buffer: Vec<char>,
+    colors: ColorMode,
+    current_color: Option<Color>,
+    current_bg_color: Option<Color>,
+}
+
+impl LineRenderer {
+    fn new(colors: ColorMode) -> Self {
+        Self {
+            buffer: Vec::new(),
+            colors,
+            current_color: None,
+            current_bg_color: None,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.buffer.clear();
+        self.current_color = None;
+        self.current_bg_color = None;
+    }
+
+    fn display(&mut self, item: &dyn Display) {
+        self.buffer
+            .append(format!("{}", item).chars().collect::<Vec<char>>().as_mut());
+    }
+
+    fn add(&mut self, cell: Cell) {
+        let Cell { chr, fg, bg } = cell;
+        if (self.current_color != fg || self.current_bg_color != bg) && !self.buffer.is_empty() {
+            self.display(&style::ResetColor);
+            // The reset cleared both colors, so they must be set again
+            self.current_color = None;
+            self.current_bg_color = None;
+        }
+        if let Some(color) = fg.filter(|_| self.current_color != fg) {
+            self.display(&set_color(color, self.colors, false));
+            self.current_color = fg;
+        }
+        if let Some(color) = bg.filter(|_| self.current_bg_color != bg) {
+            self.display(&set_color(color, self.colors, true));
+            self.current_bg_color = bg;
+        }
+        self.buffer.push(chr);
+    }
+
+    fn build(&mut self) -> String {
+        if self.colors != ColorMode::None {
+            self.display(&style::ResetColor);
+        }
+        self.buffer.iter().collect()
+    }
+}
+
+/// Redraws cell grids in place, only emitting the cells that changed since the last frame.
+/// Frames are drawn from the cursor's line, and the cursor is left on the line after them.
+#[derive(Debug, Default)]
+pub struct FrameBuffer {
+    previous: Option<CellGrid>,
+}
+
+impl FrameBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn draw(&mut self, out: &mut dyn Write, grid: &CellGrid) -> Result<(), std::io::Error> {
+        write!(out, "{}", BEGIN_SYNCHRONIZED_UPDATE)?;
+        match &self.previous {
+            Some(previous)
+                if previous.height() == grid.height() && previous.width() == grid.width() =>
+            {
+                self.draw_changes(out, previous, grid)?
+            }
+            previous => {
+                if let Some(height) = previous.as_ref().map(|p| p.height()).filter(|h| *h > 0) {
+                    write!(out, "{}", cursor::MoveToPreviousLine(height as u16))?;
+                }
+                let newline = format!("{}\r\n", Clear(ClearType::UntilNewLine));
+                grid.write(out, &newline)?;
+                // Remove what is left of a taller previous frame
+                write!(out, "{}", Clear(ClearType::FromCursorDown))?;
+            }
+        }
+        write!(out, "{}", END_SYNCHRONIZED_UPDATE)?;
+        out.flush()?;
+        self.previous = Some(grid.clone());
+        Ok(())
+    }
+
+    fn draw_changes(
+        &self,
+        out: &mut dyn Write,
+        previous: &CellGrid,
+        grid: &CellGrid,
+    ) -> Result<(), std::io::Error> {
+        let height = grid.height();
+        if height == 0 {
+            return Ok(());
+        }
+        write!(out, "{}", cursor::MoveToPreviousLine(height as u16))?;
+        let mut renderer = LineRenderer::new(grid.colors);
+        let mut line = 0;
+        for (y, (old, new)) in previous.rows.iter().zip(&grid.rows).enumerate() {
+            let mut x = 0;
+            while x < new.len() {
+                if old.get(x) == Some(&new[x]) {
+                    x += 1;
+                    continue;
+                }
+                let start = x;
+                while x < new.len() && old.get(x) != Some(&new[x]) {
+                    renderer.add(new[x]);
+                    x += 1;
+                }
+                if y > line {
+                    write!(out, "{}", cursor::MoveDown((y - line) as u16))?;
+                    line = y;
+                }
+                write!(
+                    out,
+                    "{}{}",
+                    cursor::MoveToColumn(start as u16),
+                    renderer.build()
+                )?;
+                renderer.clear();
+            }
+        }
+        write!(out, "{}", cursor::MoveToNextLine((height - line) as u16))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn color_after_blank_cell() {
+        // The blank cell resets both colors, so the next cell must set its color again
+        let red = Some(Rgb([255, 0, 0]));
+        let mut grid = CellGrid::new(ColorMode::TrueColor);
+        grid.add('⣿', red, None);
+        grid.add(' ', None, None);
+        grid.add('⣿', red, None);
+        grid.end_line();
+        let mut out = Vec::new();
+        grid.write(&mut out, "\n").unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\x1b[38;2;255;0;0m⣿\x1b[0m \x1b[0m\x1b[38;2;255;0;0m⣿\x1b[0m\n"
+        );
+    }
+}
@@ -0,0 +1,293 @@
+// ======================== Gallery ========================
+
+use std::{io::Write, path::Path};
+
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    style::Color,
+    terminal::{self, Clear, ClearType},
+};
+use image::RgbaImage;
+
+use termimgview::{
+    filter::{Filter, Pipeline},
+    frame::{Cell, CellGrid, FrameBuffer},
+    load_image, ColorMode, Error, Result, STDIN,
+};
+
+use crate::interactive::{self, View};
+
+/// Smallest width of a thumbnail in cells, thumbnails are widened to fill the terminal
+const THUMBNAIL_WIDTH: u32 = 24;
+/// Blank cells between neighbouring thumbnails
+const GAP: u32 = 2;
+
+const HELP: &str = "arrows/hjkl select  Enter open  q quit";
+
+const BLANK: Cell = Cell {
+    chr: ' ',
+    fg: None,
+    bg: None,
+};
+
+/// How thumbnails are laid out on a terminal
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Layout {
+    per_row: usize,
+    /// Size of a thumbnail in cells, without its caption
+    width: u32,
+    height: u32,
+    /// Rows of thumbnails that fit on the screen
+    visible_rows: usize,
+}
+
+impl Layout {
+    fn new((columns, rows): (u16, u16), aspect_ratio: f32) -> Self {
+        let (columns, rows) = (columns as u32, rows as u32);
+        let per_row = ((columns + GAP) / (THUMBNAIL_WIDTH + GAP)).max(1);
+        let width = ((columns + GAP) / per_row).saturating_sub(GAP).max(1);
+        // Every row of thumbnails has a caption and a blank line below, and the status line is last
+        let height = ((width as f32 * aspect_ratio).round() as u32)
+            .min(rows.saturating_sub(3))
+            .max(1);
+        Self {
+            per_row: per_row as usize,
+            width,
+            height,
+            visible_rows: (rows.saturating_sub(1) / (height + 2)).max(1) as usize,
+        }
+    }
+
+    /// Width of a full row of thumbnails in cells
+    fn row_width(&self) -> usize {
+        self.per_row * (self.width + GAP) as usize - GAP as usize
+    }
+}
+
+/// Browse `paths` as a grid of thumbnails, opening the selected image with Enter.
+/// Images are passed through `pipeline` when opened.
+/// `render` renders a view of an image into a grid of at most the given columns and rows.
+pub fn run(
+    out: &mut dyn Write,
+    paths: &[String],
+    view: View,
+    aspect_ratio: f32,
+    colors: ColorMode,
+    pipeline: &Pipeline,
+    mut render: impl FnMut(&RgbaImage, &View, (u32, u32)) -> Result<CellGrid>,
+) -> Result<()> {
+    interactive::full_screen(out, |out| {
+        browse(
+            out,
+            paths,
+            &view,
+            aspect_ratio,
+            colors,
+            pipeline,
+            &mut render,
+        )
+    })
+}
+
+fn browse(
+    out: &mut dyn Write,
+    paths: &[String],
+    view: &View,
+    aspect_ratio: f32,
+    colors: ColorMode,
+    pipeline: &Pipeline,
+    render: &mut impl FnMut(&RgbaImage, &View, (u32, u32)) -> Result<CellGrid>,
+) -> Result<()> {
+    // Stdin can only be read once, so its image is kept for every thumbnail and opening
+    let stdin = paths
+        .iter()
+        .any(|path| path == STDIN)
+        .then(|| open(STDIN, pipeline))
+        .flatten();
+    let open = |path: &str| match path {
+        STDIN => stdin.clone(),
+        path => open(path, pipeline),
+    };
+    let mut thumbnails: Vec<Option<CellGrid>> = vec![None; paths.len()];
+    let mut rendered_for = None;
+    let mut frame_buffer = FrameBuffer::new();
+    let mut selected = 0;
+    // The first visible row of thumbnails
+    let mut scroll = 0;
+    let mut redraw = true;
+    let mut clear = true;
+    loop {
+        if redraw {
+            let size = terminal::size().map_err(Error::Terminal)?;
+            let layout = Layout::new(size, aspect_ratio);
+            if rendered_for != Some(layout) {
+                thumbnails.fill(None);
+                rendered_for = Some(layout);
+            }
+            if clear {
+                frame_buffer = FrameBuffer::new();
+                write!(out, "{}{}", Clear(ClearType::All), cursor::MoveTo(0, 0))?;
+                clear = false;
+            }
+            let row = selected / layout.per_row;
+            scroll = scroll.clamp((row + 1).saturating_sub(layout.visible_rows), row);
+            let first = scroll * layout.per_row;
+            let last = ((scroll + layout.visible_rows) * layout.per_row).min(paths.len());
+            for i in first..last {
+                if thumbnails[i].is_none() {
+                    let thumbnail = open(&paths[i])
+                        .map(|img| {
+                            let view = View::new(
+                                img.dimensions(),
+                                view.shading.clone(),
+                                view.grayscale,
+                                view.invert,
+                            );
+                            render(&img, &view, (layout.width, layout.height))
+                        })
+                        .transpose()?
+                        .unwrap_or_else(|| CellGrid::new(colors));
+                    thumbnails[i] = Some(thumbnail);
+                }
+            }
+            let screen = compose(&layout, &thumbnails, paths, scroll, selected, colors);
+            frame_buffer.draw(out, &screen)?;
+            let status = format!(
+                "{}/{}  {}  {}",
+                selected + 1,
+                paths.len(),
+                paths[selected],
+                HELP
+            );
+            interactive::write_status(out, &status)?;
+            redraw = false;
+        }
+        let key = match event::read().map_err(Error::Terminal)? {
+            Event::Key(key) if key.kind != KeyEventKind::Release => key,
+            Event::Resize(..) => {
+                clear = true;
+                redraw = true;
+                continue;
+            }
+            _ => continue,
+        };
+        let per_row = Layout::new(terminal::size().map_err(Error::Terminal)?, aspect_ratio).per_row;
+        let previous = selected;
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Left | KeyCode::Char('h') => selected = selected.saturating_sub(1),
+            KeyCode::Right | KeyCode::Char('l') => selected = (selected + 1).min(paths.len() - 1),
+            KeyCode::Up | KeyCode::Char('k') => {
+                selected = selected.checked_sub(per_row).unwrap_or(selected)
+            }
+            // Move to the last image when the next row is not full
+            KeyCode::Down | KeyCode::Char('j')
+                if selected / per_row < (paths.len() - 1) / per_row =>
+            {
+                selected = (selected + per_row).min(paths.len() - 1)
+            }
+            KeyCode::Enter => {
+                if let Some(img) = open(&paths[selected]) {
+                    let view = View::new(
+                        img.dimensions(),
+                        view.shading.clone(),
+                        view.grayscale,
+                        view.invert,
+                    );
+                    interactive::event_loop(out, img.dimensions(), view, |view, cells| {
+                        render(&img, view, cells)
+                    })?;
+                    clear = true;
+                    redraw = true;
+                }
+            }
+            _ => {}
+        }
+        redraw |= selected != previous;
+    }
+}
+
+/// Open an image and apply the pipeline, leaving unreadable files without a thumbnail
+fn open(path: &str, pipeline: &Pipeline) -> Option<RgbaImage> {
+    let mut img = load_image(path).ok()?;
+    pipeline.apply(&mut img);
+    Some(img)
+}
+
+/// Lay out the visible thumbnails with their captions below them
+fn compose(
+    layout: &Layout,
+    thumbnails: &[Option<CellGrid>],
+    paths: &[String],
+    scroll: usize,
+    selected: usize,
+    colors: ColorMode,
+) -> CellGrid {
+    let mut screen = CellGrid::new(colors);
+    let width = layout.width as usize;
+    let end_line = |screen: &mut CellGrid, length: usize| {
+        // Full lines, so cells left over from the previous screen are always overwritten
+        for _ in length..layout.row_width() {
+            screen.push(BLANK);
+        }
+        screen.end_line();
+    };
+    for row in scroll..scroll + layout.visible_rows {
+        let items: Vec<usize> =
+            (row * layout.per_row..((row + 1) * layout.per_row).min(paths.len())).collect();
+        if items.is_empty() {
+            break;
+        }
+        for y in 0..layout.height as usize {
+            let mut length = 0;
+            for (n, i) in items.iter().enumerate() {
+                if n > 0 {
+                    (0..GAP).for_each(|_| screen.push(BLANK));
+                    length += GAP as usize;
+                }
+                let thumbnail = thumbnails[*i].as_ref();
+                let line = thumbnail.and_then(|t| t.rows.get(y));
+                // Center the thumbnail in its box
+                let padding = width.saturating_sub(thumbnail.map_or(0, |t| t.width())) / 2;
+                for x in 0..width {
+                    let cell = x
+                        .checked_sub(padding)
+                        .and_then(|x| line.and_then(|line| line.get(x)));
+                    screen.push(cell.copied().unwrap_or(BLANK));
+                }
+                length += width;
+            }
+            end_line(&mut screen, length);
+        }
+        let mut length = 0;
+        for (n, i) in items.iter().enumerate() {
+            if n > 0 {
+                (0..GAP).for_each(|_| screen.push(BLANK));
+                length += GAP as usize;
+            }
+            let name = Path::new(&paths[*i])
+                .file_name()
+                .map_or(paths[*i].clone(), |name| {
+                    name.to_string_lossy().into_owned()
+                });
+            let marker = if *i == selected { '>' } else { ' ' };
+            let (fg, bg) = if *i == selected && colors != ColorMode::None {
+                (Some(Color::AnsiValue(0)), Some(Color::AnsiValue(7)))
+            } else {
+                (None, None)
+            };
+            let caption = std::iter::once(marker)
+                .chain(name.chars())
+                .chain(std::iter::repeat(' '));
+            for chr in caption.take(width) {
+                screen.push(Cell { chr, fg, bg });
+            }
+            length += width;
+        }
+        end_line(&mut screen, length);
+        end_line(&mut screen, 0);
+    }
+    screen
+}
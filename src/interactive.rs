@@ -0,0 +1,244 @@
+// ======================== Interactive viewer ========================
+
+use std::io::Write;
+
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+    QueueableCommand,
+};
+
+use termimgview::{
+    frame::{CellGrid, FrameBuffer},
+    Error, Result, ShadeMethod, Threshold,
+};
+
+/// Zoom factor of a single `+` or `-` press
+const ZOOM_STEP: f32 = 1.25;
+/// Fraction of the visible region moved by a single pan
+const PAN_STEP: f32 = 0.125;
+
+const HELP: &str =
+    "+/- zoom  arrows/hjkl pan  0 reset  m shade method  g grayscale  i invert  q quit";
+
+/// What part of the image is shown, and how
+#[derive(Debug, Clone)]
+pub struct View {
+    /// Magnification relative to the whole image, at least 1
+    pub zoom: f32,
+    /// Center of the visible region in image pixels
+    pub center: (f32, f32),
+    pub shading: ShadeMethod,
+    pub grayscale: bool,
+    pub invert: bool,
+}
+
+impl View {
+    pub fn new(
+        dimensions: (u32, u32),
+        shading: ShadeMethod,
+        grayscale: bool,
+        invert: bool,
+    ) -> Self {
+        Self {
+            zoom: 1.0,
+            center: (dimensions.0 as f32 / 2.0, dimensions.1 as f32 / 2.0),
+            shading,
+            grayscale,
+            invert,
+        }
+    }
+
+    /// The visible region of an image of `dimensions` pixels, as `(x, y, width, height)`
+    pub fn crop(&self, (width, height): (u32, u32)) -> (u32, u32, u32, u32) {
+        // At least a pixel, unless the image has none
+        let visible_width = (width as f32 / self.zoom)
+            .round()
+            .max(1.0)
+            .min(width as f32);
+        let visible_height = (height as f32 / self.zoom)
+            .round()
+            .max(1.0)
+            .min(height as f32);
+        let x = (self.center.0 - visible_width / 2.0).clamp(0.0, width as f32 - visible_width);
+        let y = (self.center.1 - visible_height / 2.0).clamp(0.0, height as f32 - visible_height);
+        (
+            x.round() as u32,
+            y.round() as u32,
+            visible_width as u32,
+            visible_height as u32,
+        )
+    }
+
+    fn zoom(&mut self, factor: f32, dimensions: (u32, u32)) {
+        // Zooming in further than a single pixel shows nothing new
+        let max_zoom = dimensions.0.max(dimensions.1) as f32;
+        self.zoom = (self.zoom * factor).clamp(1.0, max_zoom.max(1.0));
+        self.clamp_center(dimensions);
+    }
+
+    /// Move the visible region by a fraction of its size
+    fn pan(&mut self, dx: f32, dy: f32, dimensions: (u32, u32)) {
+        let (_, _, width, height) = self.crop(dimensions);
+        self.center.0 += dx * width as f32;
+        self.center.1 += dy * height as f32;
+        self.clamp_center(dimensions);
+    }
+
+    /// Keep the center where the visible region stays inside the image
+    fn clamp_center(&mut self, dimensions: (u32, u32)) {
+        let (x, y, width, height) = self.crop(dimensions);
+        self.center = (
+            x as f32 + width as f32 / 2.0,
+            y as f32 + height as f32 / 2.0,
+        );
+    }
+
+    fn next_shading(&mut self) {
+        let methods = [
+            ShadeMethod::Ascii,
+            ShadeMethod::Blocks,
+            ShadeMethod::Half,
+            ShadeMethod::Quadrant,
+            ShadeMethod::Sextant,
+            ShadeMethod::Braille(Threshold::Otsu),
+            ShadeMethod::Shape,
+        ];
+        let current = methods
+            .iter()
+            .position(|m| m.to_string() == self.shading.to_string());
+        // Custom ramps are left for the first built-in method
+        self.shading = match current {
+            Some(i) => methods[(i + 1) % methods.len()].clone(),
+            None => methods[0].clone(),
+        };
+    }
+}
+
+/// Show the image on the alternate screen until `q`, `Esc` or Ctrl-C is pressed.
+/// `render` renders the view into a grid of at most the given columns and rows.
+pub fn run(
+    out: &mut dyn Write,
+    dimensions: (u32, u32),
+    view: View,
+    render: impl FnMut(&View, (u32, u32)) -> Result<CellGrid>,
+) -> Result<()> {
+    full_screen(out, |out| event_loop(out, dimensions, view, render))
+}
+
+/// Run `f` on the alternate screen with the terminal in raw mode, restoring both afterwards
+pub fn full_screen<T>(
+    out: &mut dyn Write,
+    f: impl FnOnce(&mut dyn Write) -> Result<T>,
+) -> Result<T> {
+    // When stdin is a pipe, crossterm reads keys from the controlling terminal instead
+    terminal::enable_raw_mode().map_err(Error::Terminal)?;
+    // The alternate screen leaves the scrollback untouched
+    let result = match out
+        .queue(EnterAlternateScreen)
+        .and_then(|out| out.queue(cursor::Hide))
+    {
+        Ok(_) => f(out),
+        Err(error) => Err(error.into()),
+    };
+    let restored = out
+        .queue(cursor::Show)
+        .and_then(|out| out.queue(LeaveAlternateScreen))
+        .and_then(|out| out.flush());
+    // Leave raw mode even when the terminal could not be written to
+    terminal::disable_raw_mode().map_err(Error::Terminal)?;
+    let value = result?;
+    restored?;
+    Ok(value)
+}
+
+/// Let the user pan and zoom around the image until they quit, on a screen that is already set up
+pub fn event_loop(
+    out: &mut dyn Write,
+    dimensions: (u32, u32),
+    mut view: View,
+    mut render: impl FnMut(&View, (u32, u32)) -> Result<CellGrid>,
+) -> Result<()> {
+    let mut frame_buffer = FrameBuffer::new();
+    let mut redraw = true;
+    // Nothing on the screen can be reused after a resize
+    let mut clear = true;
+    loop {
+        if redraw {
+            let (columns, rows) = terminal::size().map_err(Error::Terminal)?;
+            if clear {
+                frame_buffer = FrameBuffer::new();
+                write!(out, "{}{}", Clear(ClearType::All), cursor::MoveTo(0, 0))?;
+                clear = false;
+            }
+            // The last row is kept for the status line
+            let grid = render(
+                &view,
+                (columns as u32, rows.saturating_sub(1).max(1) as u32),
+            )?;
+            frame_buffer.draw(out, &grid)?;
+            let status = format!("{:.2}x  {}  {}", view.zoom, view.shading, HELP);
+            write_status(out, &status)?;
+            redraw = false;
+        }
+        let key = match event::read().map_err(Error::Terminal)? {
+            Event::Key(key) if key.kind != KeyEventKind::Release => key,
+            Event::Resize(..) => {
+                clear = true;
+                redraw = true;
+                continue;
+            }
+            _ => continue,
+        };
+        redraw = true;
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Char('+') | KeyCode::Char('=') => view.zoom(ZOOM_STEP, dimensions),
+            KeyCode::Char('-') => view.zoom(1.0 / ZOOM_STEP, dimensions),
+            KeyCode::Left | KeyCode::Char('h') => view.pan(-PAN_STEP, 0.0, dimensions),
+            KeyCode::Right | KeyCode::Char('l') => view.pan(PAN_STEP, 0.0, dimensions),
+            KeyCode::Up | KeyCode::Char('k') => view.pan(0.0, -PAN_STEP, dimensions),
+            KeyCode::Down | KeyCode::Char('j') => view.pan(0.0, PAN_STEP, dimensions),
+            KeyCode::Char('0') => {
+                view = View::new(dimensions, view.shading, view.grayscale, view.invert)
+            }
+            KeyCode::Char('m') => view.next_shading(),
+            KeyCode::Char('g') => view.grayscale = !view.grayscale,
+            KeyCode::Char('i') => view.invert = !view.invert,
+            _ => redraw = false,
+        }
+    }
+}
+
+/// Write a line of text on the last row of the screen, leaving the cursor where it was
+pub fn write_status(out: &mut dyn Write, status: &str) -> Result<()> {
+    let (columns, rows) = terminal::size().map_err(Error::Terminal)?;
+    write!(
+        out,
+        "{}{}{}{}{}",
+        cursor::SavePosition,
+        cursor::MoveTo(0, rows.saturating_sub(1)),
+        Clear(ClearType::CurrentLine),
+        status.chars().take(columns as usize).collect::<String>(),
+        cursor::RestorePosition
+    )?;
+    Ok(out.flush()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crop() {
+        let mut view = View::new((100, 50), ShadeMethod::Blocks, false, false);
+        assert_eq!(view.crop((100, 50)), (0, 0, 100, 50));
+        view.zoom(2.0, (100, 50));
+        assert_eq!(view.crop((100, 50)), (25, 13, 50, 25));
+        // Images without pixels, like those of the gallery before loading
+        let view = View::new((0, 0), ShadeMethod::Blocks, false, false);
+        assert_eq!(view.crop((0, 0)), (0, 0, 0, 0));
+    }
+}
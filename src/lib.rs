@@ -0,0 +1,224 @@
+//! Rendering of images to terminals, as text or with graphics protocols.
+//!
+//! [`Viewer`] is the entry point for drawing an image, while [`processing`] and [`rendering`]
+//! give access to the individual filters and renderers.
+//!
+//! ```no_run
+//! use termimgview::{load_image, ShadeMethod, Viewer};
+//!
+//! let img = load_image("cat.png")?;
+//! Viewer::new(&img)
+//!     .shade(ShadeMethod::Half)
+//!     .size(40, 20)
+//!     .render_to(&mut std::io::stdout())?;
+//! # Ok::<(), termimgview::Error>(())
+//! ```
+
+use std::{fmt::Display, io::Read};
+
+use image::{ImageFormat, RgbaImage};
+
+pub mod ansi;
+pub mod detection;
+mod error;
+pub mod export;
+pub mod filter;
+mod font;
+pub mod frame;
+pub mod processing;
+pub mod renderer;
+pub mod rendering;
+pub mod sizing;
+mod viewer;
+
+pub use error::{Error, Result};
+pub use renderer::Renderer;
+pub use viewer::Viewer;
+
+// <Width> / <Height> = <Font aspect ratio>, used when the terminal does not report its cell size
+pub const FONT_ASPECT_RATIO: f32 = 8.0 / 17.0; // or 2.0 / 3.0;
+
+#[derive(Debug, Clone)]
+pub enum ShadeMethod {
+    Ascii,
+    Blocks,
+    Half,
+    Quadrant,
+    Sextant,
+    Braille(Threshold),
+    Shape,
+    Custom(Option<String>),
+}
+
+impl Display for ShadeMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShadeMethod::Ascii => write!(f, "ascii"),
+            ShadeMethod::Blocks => write!(f, "blocks"),
+            ShadeMethod::Half => write!(f, "half"),
+            ShadeMethod::Quadrant => write!(f, "quadrant"),
+            ShadeMethod::Sextant => write!(f, "sextant"),
+            ShadeMethod::Braille(_) => write!(f, "braille"),
+            ShadeMethod::Shape => write!(f, "shape"),
+            ShadeMethod::Custom(_) => write!(f, "custom"),
+        }
+    }
+}
+
+/// How many colors the terminal can show
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorMode {
+    TrueColor,
+    Ansi256,
+    Ansi16,
+    None,
+}
+
+impl Display for ColorMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColorMode::TrueColor => write!(f, "truecolor"),
+            ColorMode::Ansi256 => write!(f, "256"),
+            ColorMode::Ansi16 => write!(f, "16"),
+            ColorMode::None => write!(f, "none"),
+        }
+    }
+}
+
+/// How colors and luminance are spread over neighbouring pixels when quantizing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dither {
+    None,
+    FloydSteinberg,
+    Atkinson,
+    Sierra,
+    /// Ordered dithering with a Bayer matrix of the given size
+    Bayer(u32),
+}
+
+impl Display for Dither {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Dither::None => write!(f, "none"),
+            Dither::FloydSteinberg => write!(f, "floyd-steinberg"),
+            Dither::Atkinson => write!(f, "atkinson"),
+            Dither::Sierra => write!(f, "sierra"),
+            Dither::Bayer(size) => write!(f, "bayer{}", size),
+        }
+    }
+}
+
+/// How the image is resampled to the pixels of its cells
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resample {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+    /// The mean of all source pixels covered, in linear light
+    Area,
+}
+
+impl Display for Resample {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Resample::Nearest => write!(f, "nearest"),
+            Resample::Triangle => write!(f, "triangle"),
+            Resample::CatmullRom => write!(f, "catmull-rom"),
+            Resample::Gaussian => write!(f, "gaussian"),
+            Resample::Lanczos3 => write!(f, "lanczos3"),
+            Resample::Area => write!(f, "area"),
+        }
+    }
+}
+
+/// How sub-cell pixels are split into lit and unlit dots
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Threshold {
+    /// Pixels brighter than a fixed luminance
+    Fixed(u8),
+    /// Pixels brighter than the mean luminance of their cell
+    Mean,
+    /// Otsu's method, maximizing the variance between lit and unlit pixels of each cell
+    Otsu,
+}
+
+impl Display for Threshold {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Threshold::Fixed(value) => write!(f, "{}", value),
+            Threshold::Mean => write!(f, "mean"),
+            Threshold::Otsu => write!(f, "otsu"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    Text,
+    Sixel,
+    Kitty,
+    Iterm,
+}
+
+impl Display for Protocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Protocol::Text => write!(f, "text"),
+            Protocol::Sixel => write!(f, "sixel"),
+            Protocol::Kitty => write!(f, "kitty"),
+            Protocol::Iterm => write!(f, "iterm"),
+        }
+    }
+}
+
+impl Protocol {
+    /// Whether the protocol draws real pixels instead of character cells
+    pub fn is_graphics(&self) -> bool {
+        !matches!(self, Protocol::Text)
+    }
+}
+
+// ======================== Loading ========================
+
+/// Path that reads the image from stdin
+pub const STDIN: &str = "-";
+/// Path that writes the output to stdout
+pub const STDOUT: &str = "-";
+
+/// Read the bytes of the image at `path`, or of stdin for `-`
+pub fn read_input(path: &str) -> Result<Vec<u8>> {
+    let bytes = if path == STDIN {
+        let mut bytes = Vec::new();
+        std::io::stdin().read_to_end(&mut bytes).map(|_| bytes)
+    } else {
+        std::fs::read(path)
+    };
+    bytes.map_err(|source| Error::Io {
+        path: Some(path.to_string()),
+        source,
+    })
+}
+
+/// The format of an image from the extension of its path, or else from its contents
+pub fn image_format(path: &str, bytes: &[u8]) -> Option<ImageFormat> {
+    ImageFormat::from_path(path)
+        .ok()
+        .or_else(|| image::guess_format(bytes).ok())
+}
+
+/// Decode the image read from `path`
+pub fn decode_image(path: &str, bytes: &[u8], format: Option<ImageFormat>) -> Result<RgbaImage> {
+    let format = format.ok_or_else(|| Error::UnsupportedFormat {
+        path: path.to_string(),
+    })?;
+    image::load_from_memory_with_format(bytes, format)
+        .map(|img| img.to_rgba8())
+        .map_err(|source| Error::decode(path, source))
+}
+
+pub fn load_image(path: &str) -> Result<RgbaImage> {
+    let bytes = read_input(path)?;
+    decode_image(path, &bytes, image_format(path, &bytes))
+}
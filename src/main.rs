@@ -1,42 +1,26 @@
-use std::fmt::Display;
+use std::{
+    collections::HashMap,
+    io::{IsTerminal, Write},
+    path::Path,
+    time::Duration,
+};
 
-use clap::{self, command, error::ErrorKind, CommandFactory, Parser};
-use crossterm::{style, style::Color};
-use image::{open, ImageBuffer, Rgb, Rgba};
+use clap::{
+    self, error::ErrorKind, parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches, Parser,
+};
+use crossterm::terminal::{Clear, ClearType};
+use image::{ImageFormat, Rgb, RgbaImage};
+use termimgview::{
+    ansi, detection, export,
+    filter::{self, Filter, Pipeline},
+    frame, processing, renderer, rendering, sizing, ColorMode, Dither, Error, Protocol, Renderer,
+    Resample, Result, ShadeMethod, Threshold, Viewer, FONT_ASPECT_RATIO, STDIN, STDOUT,
+};
 
-mod processing;
-mod rendering;
-
-// <Width> / <Height> = <Font aspect ratio>
-const FONT_ASPECT_RATIO: f32 = 8.0 / 17.0; // or 2.0 / 3.0;
-
-#[derive(Debug, Clone)]
-pub enum ShadeMethod {
-    Ascii,
-    Blocks,
-    Half,
-    Custom(Option<String>),
-}
-
-impl Display for ShadeMethod {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            ShadeMethod::Ascii => write!(f, "ascii"),
-            ShadeMethod::Blocks => write!(f, "blocks"),
-            ShadeMethod::Half => write!(f, "half"),
-            ShadeMethod::Custom(_) => write!(f, "custom"),
-        }
-    }
-}
-
-impl ShadeMethod {
-    pub fn height_multiplier(&self) -> f32 {
-        match self {
-            ShadeMethod::Half => 2.0,
-            _ => 1.0,
-        }
-    }
-}
+mod animation;
+mod config;
+mod gallery;
+mod interactive;
 
 // ======================== CLI ========================
 
@@ -46,31 +30,89 @@ impl ShadeMethod {
     version = env!("CARGO_PKG_VERSION"),
     author = env!("CARGO_PKG_AUTHORS"),
     about = format!("{}\nby {}", env!("CARGO_PKG_DESCRIPTION"), env!("CARGO_PKG_AUTHORS")),
+    args_override_self = true,
     after_help = format!(
         "Shade methods:\n{}\n\nExample usage:\n - {} .\\tests\\1.png -s 0.15 -m \" -:!|#@@@@@@@@\"\n - {} .\\tests\\2.jpg -s 1 -i -m ascii",
         processing::SHADE_METHOD.iter().enumerate().map(|(_, (i, s))| format!(" - {}: '{}'", i, s)).collect::<Vec<String>>().join("\n"),
         env!("CARGO_PKG_NAME"),
-        env!("CARGO_PKG_NAME")),
-    arg_required_else_help = true)]
+        env!("CARGO_PKG_NAME")))]
 struct Cli {
-    #[clap(help = "Path to the image file to be displayed")]
-    file: String,
+    #[clap(
+        help = "Paths, globs or directories of the images to be displayed, - reads from stdin [default: - when stdin is not a terminal]"
+    )]
+    files: Vec<String>,
     #[clap(
         short = 'm',
         long,
-        value_enum,
-        default_value = "blocks",
-        help = "Shading method"
+        help = "Shading method [default: blocks, or detected with --protocol auto]"
+    )]
+    shade_method: Option<String>,
+    #[clap(
+        short = 'p',
+        long,
+        default_value = "auto",
+        help = "Output protocol (auto, text, sixel, kitty, iterm)"
+    )]
+    protocol: String,
+    #[clap(
+        long,
+        default_value = "otsu",
+        help = "Dot threshold for braille (otsu, mean or a luminance 0-255)"
     )]
-    shade_method: String,
+    threshold: String,
+    #[clap(
+        short = 'z',
+        long,
+        default_value = "0",
+        allow_negative_numbers = true,
+        help = "Stacking order of graphics placements (kitty)"
+    )]
+    z_index: i32,
+    #[clap(
+        long,
+        help = "Size of graphics placements in cells as <COLUMNS>x<ROWS>, scaled by the terminal (kitty, iterm)"
+    )]
+    placement: Option<String>,
+    #[clap(
+        long,
+        default_value = "auto",
+        help = "Colors of text output (auto, truecolor, 256, 16, none)"
+    )]
+    colors: String,
+    #[clap(
+        long,
+        default_value = "none",
+        help = "Dithering of luminance ramps and reduced colors (none, floyd-steinberg, atkinson, sierra, bayer2, bayer4, bayer8)"
+    )]
+    dither: String,
     #[clap(short, long, default_value = "1", help = "The scale of the image")]
     scale: f32,
+    #[clap(short = 'W', long, help = "Width in character cells")]
+    width: Option<u32>,
+    #[clap(short = 'H', long, help = "Height in character cells")]
+    height: Option<u32>,
+    #[clap(
+        long,
+        default_value = "contain",
+        help = "Fitting into the terminal or --width and --height (contain, cover, stretch, none)"
+    )]
+    fit: String,
+    #[clap(
+        long,
+        default_value = "area",
+        help = "Resampling filter (nearest, triangle, catmull-rom, gaussian, lanczos3, area)"
+    )]
+    resample: String,
     #[clap(short, long, default_value = "false", help = "Grayscale image?")]
     grayscale: bool,
     #[clap(short, long, default_value = "false", help = "Invert image?")]
     invert: bool,
-    #[clap(short, long, default_value_t = FONT_ASPECT_RATIO, help = "Adjust aspect ratio")]
-    adjust_aspect_ratio: f32,
+    #[clap(
+        short,
+        long,
+        help = "Adjust aspect ratio [default: queried from the terminal, or 0.47058824]"
+    )]
+    adjust_aspect_ratio: Option<f32>,
     #[clap(
         short = 'b',
         long,
@@ -93,7 +135,7 @@ struct Cli {
         default_value = "0,0,0",
         help = "Make color transparent"
     )]
-    rm_color: String, // resolution_multiplier: f32,
+    rm_color: String,
     // Color removal tolerance
     #[clap(
         short = 't',
@@ -102,89 +144,663 @@ struct Cli {
         help = "Color removal tolerance"
     )]
     rm_tolerance: f32,
+    #[clap(
+        long,
+        help = "Filters applied in order before resizing, e.g. \"crop=10,10,200,200|hue=90|contrast=1.4|invert\""
+    )]
+    filter: Option<String>,
+    #[clap(
+        long = "loop",
+        default_value = "0",
+        help = "Number of times to play animations, 0 loops forever"
+    )]
+    loop_count: u32,
+    #[clap(long, default_value = "false", help = "Play animations once?")]
+    once: bool,
+    #[clap(
+        long,
+        default_value = "false",
+        help = "Explore the image full screen with pan and zoom?"
+    )]
+    interactive: bool,
+    #[clap(
+        long,
+        default_value = "false",
+        help = "Browse the images as a grid of thumbnails?"
+    )]
+    gallery: bool,
+    #[clap(
+        short = 'o',
+        long,
+        help = "Write the image to a file instead of the terminal, as HTML (.html), SVG (.svg) or escape sequences (.ans, or - for stdout)"
+    )]
+    output: Option<String>,
+    #[clap(
+        long,
+        default_value = "false",
+        help = "Write --output as classic ANSI art in CP437 with 16 colors and a SAUCE record?"
+    )]
+    cp437: bool,
+    #[clap(
+        long,
+        help = "Options from a [preset.<PRESET>] table of the configuration file"
+    )]
+    preset: Option<String>,
 }
 
-fn args() -> (Cli, ShadeMethod, Option<Rgb<u8>>) {
-    let args = Cli::parse();
-    let shading = match args.shade_method.to_lowercase().as_str() {
-        "ascii" => ShadeMethod::Ascii,
-        "blocks" => ShadeMethod::Blocks,
-        "half" => ShadeMethod::Half,
-        mapping => {
-            if !mapping.is_empty() {
-                ShadeMethod::Custom(Some(mapping.to_string()))
-            } else {
-                Cli::command()
-                    .error(
-                        ErrorKind::ValueValidation,
-                        &format!("Invalid shade method: {}", mapping),
-                    )
-                    .print()
-                    .unwrap();
-                std::process::exit(1);
-            }
+/// Display options resolved from the command line and the terminal
+struct Settings {
+    shading: ShadeMethod,
+    protocol: Protocol,
+    colors: ColorMode,
+    dither: Dither,
+    fit: sizing::Fit,
+    resample: Resample,
+    /// Cells of graphics placements
+    placement: Option<(u32, u32)>,
+    /// <Width> / <Height> of a character cell
+    aspect_ratio: f32,
+    /// Size of a character cell in pixels
+    cell_size: (f32, f32),
+    /// Cells available in the terminal, leaving a line for the prompt below the image
+    terminal: Option<(u32, u32)>,
+    /// Renderers of text by shade method
+    renderers: renderer::Registry,
+    /// Filters of the flags and `--filter` in the order they were given, applied to the image as loaded
+    pipeline: Pipeline,
+    /// Format of the file given with `--output`
+    output: Option<export::Format>,
+}
+
+fn args() -> Result<(Cli, Settings)> {
+    // Flags set in the configuration are turned off with `--no-<flag>`
+    let command = config::Config::negations(Cli::command());
+    let mut matches = command.clone().get_matches();
+    let config = config::Config::load()?;
+    let preset = matches.get_one::<String>("preset");
+    let defaults = config.arguments(&Cli::command(), preset.map(String::as_str))?;
+    if !defaults.is_empty() {
+        // The command line comes last, so its options override the configuration
+        let mut arguments = std::env::args_os();
+        matches = command.get_matches_from(
+            arguments
+                .next()
+                .into_iter()
+                .chain(defaults)
+                .chain(arguments),
+        );
+    }
+    let mut args = Cli::from_arg_matches(&matches).unwrap_or_else(|error| error.exit());
+    let ramps = config.ramps()?;
+    if args.files.is_empty() {
+        if std::io::stdin().is_terminal() {
+            Cli::command().print_help()?;
+            std::process::exit(2);
+        }
+        args.files.push(STDIN.to_string());
+    }
+    let shading = args
+        .shade_method
+        .as_ref()
+        .map(|shade_method| parse_shade_method(shade_method, &args.threshold, &ramps))
+        .transpose()?;
+    let output = args
+        .output
+        .as_deref()
+        .map(|path| {
+            export::Format::from_path(path).ok_or_else(|| {
+                Error::InvalidArgument(format!("Unsupported output format: {}", path))
+            })
+        })
+        .transpose()?;
+    let output = match output {
+        Some(export::Format::Ansi) if args.cp437 => Some(export::Format::Cp437),
+        _ if args.cp437 => {
+            return Err(Error::InvalidArgument(
+                "--cp437 needs an .ans file or - as --output".to_string(),
+            ))
         }
+        output => output,
     };
-    let remove_bg_color = {
-        if args.rm_color.is_empty() {
-            None
-        } else {
-            let mut channels = args.rm_color.split(',');
-            let mut get = |name: &str| {
-                channels
-                    .next()
-                    .unwrap_or_else(|| {
-                        panic!("Expected {} channel of background removal color", name)
-                    })
-                    .parse()
-                    .unwrap()
-            };
-            Some(Rgb::<u8>([get("red"), get("green"), get("blue")]))
+    let (protocol, shading) = match args.protocol.to_lowercase().as_str() {
+        // Files are written as text in UTF-8, whatever the terminal supports
+        _ if output.is_some() => (Protocol::Text, shading.unwrap_or(ShadeMethod::Half)),
+        "auto" => {
+            let capabilities = detection::Capabilities::detect();
+            (
+                capabilities.protocol(),
+                shading.unwrap_or_else(|| capabilities.shading()),
+            )
+        }
+        protocol => (
+            parse_protocol(protocol)?,
+            shading.unwrap_or(ShadeMethod::Blocks),
+        ),
+    };
+    let colors = match args.colors.to_lowercase().as_str() {
+        "auto" | "16" if args.cp437 => ColorMode::Ansi16,
+        _ if args.cp437 => {
+            return Err(Error::InvalidArgument(
+                "--cp437 writes 16 colors".to_string(),
+            ))
         }
+        "auto" if output.is_some() => ColorMode::TrueColor,
+        "auto" => detection::Capabilities::from_env(|name| std::env::var(name).ok()).colors,
+        colors => parse_colors(colors)?,
+    };
+    let dither = parse_dither(&args.dither)?;
+    let fit = parse_fit(&args.fit)?;
+    let resample = parse_resample(&args.resample)?;
+    let placement = args.placement.as_deref().map(parse_placement).transpose()?;
+    let pipeline = pipeline(&args, &matches)?;
+    let cell_size = detection::cell_size();
+    let aspect_ratio = args
+        .adjust_aspect_ratio
+        // Classic ANSI art is drawn in the 8x16 font of VGA text mode
+        .or(args.cp437.then_some(0.5))
+        .or(cell_size.map(|(width, height)| width / height))
+        .unwrap_or(FONT_ASPECT_RATIO);
+    let cell_size = cell_size.unwrap_or((sizing::CELL_WIDTH, sizing::CELL_WIDTH / aspect_ratio));
+    let terminal = crossterm::terminal::size()
+        .ok()
+        .filter(|_| std::io::stdout().is_terminal())
+        .map(|(columns, rows)| (columns as u32, rows.saturating_sub(1).max(1) as u32));
+    let settings = Settings {
+        shading,
+        protocol,
+        colors,
+        dither,
+        fit,
+        resample,
+        placement,
+        aspect_ratio,
+        cell_size,
+        terminal,
+        renderers: renderer::Registry::new(),
+        pipeline,
+        output,
     };
-    (args, shading, remove_bg_color)
+    Ok((args, settings))
 }
 
 fn main() {
-    let (args, shading, rm_bg_color) = args();
-    let mut img = load_image(&args.file);
-    if args.adjust_aspect_ratio != 1.0 || args.scale != 1.0 {
-        // Stretch the image in the y direction to match the font aspect ratio
-        let aspect_adjust_height = img.height() as f32 * args.adjust_aspect_ratio;
-        let scaled_width = img.width() as f32 * args.scale;
-        let scaled_height = aspect_adjust_height as f32 * args.scale;
-        let scaled_height = scaled_height * shading.height_multiplier();
-        img = image::imageops::resize(
-            &img,
-            scaled_width as u32,
-            scaled_height as u32,
-            image::imageops::FilterType::Nearest,
+    if let Err(error) = run() {
+        // The reader of the output went away, as with `| head`, so there is nobody to tell
+        if let Error::Io { path: None, source } = &error {
+            if source.kind() == std::io::ErrorKind::BrokenPipe {
+                return;
+            }
+        }
+        let kind = match error {
+            Error::InvalidArgument(_) => ErrorKind::ValueValidation,
+            Error::Decode { .. } | Error::UnsupportedFormat { .. } => ErrorKind::InvalidValue,
+            Error::Io { .. } | Error::Terminal(_) => ErrorKind::Io,
+        };
+        Cli::command().error(kind, &error).print().ok();
+        std::process::exit(error.exit_code());
+    }
+}
+
+fn run() -> Result<()> {
+    let (args, settings) = args()?;
+    let mut paths = expand_paths(&args.files)?;
+    let gallery = args.gallery && args.output.is_none() && std::io::stdout().is_terminal();
+    if gallery {
+        // ANSI art is replayed as it is, so it has no thumbnail to browse
+        paths.retain(|path| !ansi::is_ansi(path, &[]));
+    }
+    if paths.is_empty() {
+        return Err(Error::InvalidArgument("No images found".to_string()));
+    }
+    if let (Some(output), Some(format)) = (&args.output, settings.output) {
+        if paths.len() > 1 {
+            return Err(Error::InvalidArgument(
+                "Only a single image can be written to --output".to_string(),
+            ));
+        }
+        return export(&args, &settings, &paths[0], output, format);
+    }
+    if gallery {
+        // Grayscale and inversion are in the pipeline, the keys toggle them again on top
+        let view = interactive::View::new((0, 0), settings.shading.clone(), false, false);
+        return gallery::run(
+            &mut std::io::stdout(),
+            &paths,
+            view,
+            settings.aspect_ratio,
+            settings.colors,
+            &settings.pipeline,
+            |img, view, cells| render_view(&args, &settings, img, view, cells),
         );
     }
-    if args.invert {
-        processing::invert_img(&mut img);
+    let mut out = std::io::stdout();
+    for path in &paths {
+        if paths.len() > 1 {
+            writeln!(out, "{}", path)?;
+        }
+        show(&args, &settings, path)?;
+    }
+    Ok(())
+}
+
+/// Id of the kitty image that animation frames are transmitted as
+const ANIMATION_IMAGE_ID: u32 = 1;
+
+/// Display a single image, playing it if animated
+fn show(args: &Cli, settings: &Settings, path: &str) -> Result<()> {
+    let bytes = termimgview::read_input(path)?;
+    if ansi::is_ansi(path, &bytes) {
+        return ansi::replay(&mut std::io::stdout().lock(), &bytes);
+    }
+    let frames = animation::load_frames(path, &bytes)?;
+    let renderer = &*renderer(settings, &settings.shading)?;
+    if args.interactive {
+        // Filtered once, as the view is rendered again on every key
+        let mut img = frames[0].0.clone();
+        settings.pipeline.apply(&mut img);
+        let img = &img;
+        let view = interactive::View::new(img.dimensions(), settings.shading.clone(), false, false);
+        return interactive::run(
+            &mut std::io::stdout(),
+            img.dimensions(),
+            view,
+            |view, cells| render_view(args, settings, img, view, cells),
+        );
+    }
+    if frames.len() == 1 || !std::io::stdout().is_terminal() {
+        return viewer(args, settings, &frames[0].0, renderer)
+            .pipeline(&settings.pipeline)
+            .render_to(&mut std::io::stdout());
+    }
+    let delays: Vec<Duration> = frames.iter().map(|(_, delay)| *delay).collect();
+    let loops = if args.once { 1 } else { args.loop_count };
+    let mut out = std::io::stdout();
+    if settings.protocol.is_graphics() {
+        let rendered: Vec<Vec<u8>> = frames
+            .iter()
+            .map(|(img, _)| {
+                let mut buffer = Vec::new();
+                viewer(args, settings, img, renderer)
+                    .pipeline(&settings.pipeline)
+                    .image_id(ANIMATION_IMAGE_ID)
+                    .render_to(&mut buffer)?;
+                Ok(buffer)
+            })
+            .collect::<Result<_>>()?;
+        let rows = viewer(args, settings, &frames[0].0, renderer)
+            .pipeline(&settings.pipeline)
+            .layout()
+            .rows;
+        // Graphics end with a single newline, but cover all rows of their placement
+        let mut first = true;
+        animation::play(&mut out, &delays, loops, |out, i| {
+            if !first {
+                write!(
+                    out,
+                    "{}",
+                    crossterm::cursor::MoveToPreviousLine(rows as u16)
+                )?;
+                // Pixels left transparent by the next frame would show the previous one
+                match settings.protocol {
+                    Protocol::Kitty => rendering::delete_kitty_image(out, ANIMATION_IMAGE_ID)?,
+                    _ => write!(out, "{}", Clear(ClearType::FromCursorDown))?,
+                }
+            }
+            first = false;
+            animation::write_raw(out, &rendered[i])
+        })
+    } else {
+        let grids: Vec<frame::CellGrid> = frames
+            .iter()
+            .map(|(img, _)| {
+                viewer(args, settings, img, renderer)
+                    .pipeline(&settings.pipeline)
+                    .render_cells()
+            })
+            .collect::<Result<_>>()?;
+        let mut frame_buffer = frame::FrameBuffer::new();
+        animation::play(&mut out, &delays, loops, |out, i| {
+            frame_buffer.draw(out, &grids[i])
+        })
+    }
+}
+
+/// Write the cells of an image, or the first frame of an animation, to a file
+fn export(
+    args: &Cli,
+    settings: &Settings,
+    path: &str,
+    output: &str,
+    format: export::Format,
+) -> Result<()> {
+    let bytes = termimgview::read_input(path)?;
+    if ansi::is_ansi(path, &bytes) {
+        return Err(Error::InvalidArgument(format!(
+            "ANSI art cannot be exported, only images: {}",
+            path
+        )));
     }
-    if args.grayscale {
-        processing::grayscale_img(&mut img);
+    let img = termimgview::decode_image(path, &bytes, termimgview::image_format(path, &bytes))?;
+    let renderer = renderer(settings, &settings.shading)?;
+    let grid = viewer(args, settings, &img, renderer.as_ref())
+        .pipeline(&settings.pipeline)
+        .render_cells()?;
+    let mut out: Box<dyn Write> = if output == STDOUT {
+        Box::new(std::io::stdout().lock())
+    } else {
+        let file = std::fs::File::create(output).map_err(|source| Error::Io {
+            path: Some(output.to_string()),
+            source,
+        })?;
+        Box::new(std::io::BufWriter::new(file))
+    };
+    export::write(&mut out, &grid, format, settings.aspect_ratio)?;
+    Ok(out.flush()?)
+}
+
+/// Render the visible part of an interactive view as text in at most `columns` by `rows` cells
+fn render_view(
+    args: &Cli,
+    settings: &Settings,
+    img: &RgbaImage,
+    view: &interactive::View,
+    (columns, rows): (u32, u32),
+) -> Result<frame::CellGrid> {
+    let (x, y, width, height) = view.crop(img.dimensions());
+    let renderer = renderer(settings, &view.shading)?;
+    viewer(args, settings, img, renderer.as_ref())
+        .region(x, y, width, height)
+        .protocol(Protocol::Text)
+        .shade(view.shading.clone())
+        .grayscale(view.grayscale)
+        .invert(view.invert)
+        .fit(sizing::Fit::Contain)
+        .size(columns, rows)
+        .render_cells()
+}
+
+/// The renderer of a shade method, looked up by name so that registered renderers take precedence
+fn renderer(settings: &Settings, shading: &ShadeMethod) -> Result<Box<dyn Renderer>> {
+    let options = renderer::Options {
+        colors: settings.colors,
+        dither: settings.dither,
+        threshold: match shading {
+            ShadeMethod::Braille(threshold) => *threshold,
+            _ => Threshold::Otsu,
+        },
+    };
+    match settings.renderers.create(&shading.to_string(), options) {
+        Some(renderer) => Ok(renderer),
+        None => shading.renderer(options),
     }
-    if let Some(rm_bg_color) = rm_bg_color {
-        processing::remove_bg_color(&mut img, rm_bg_color, args.rm_tolerance);
+}
+
+/// A viewer of the image with all options from the command line
+fn viewer<'a>(
+    args: &Cli,
+    settings: &Settings,
+    img: &'a RgbaImage,
+    renderer: &'a dyn Renderer,
+) -> Viewer<'a> {
+    let (cell_width, cell_height) = settings.cell_size;
+    let mut viewer = Viewer::new(img)
+        .shade(settings.shading.clone())
+        .renderer(renderer)
+        .protocol(settings.protocol)
+        .colors(settings.colors)
+        .dither(settings.dither)
+        .resample(settings.resample)
+        .fit(settings.fit)
+        .scale(args.scale)
+        .cell_size(cell_width, cell_height)
+        .aspect_ratio(settings.aspect_ratio)
+        .z_index(args.z_index);
+    if let Some(columns) = args.width {
+        viewer = viewer.width(columns);
     }
-    if args.brightness != 1 {
-        processing::brightness_img(&mut img, args.brightness);
+    if let Some(rows) = args.height {
+        viewer = viewer.height(rows);
     }
-    if args.contrast != 1.0 {
-        processing::contrast_img(&mut img, args.contrast);
+    if let Some((columns, rows)) = settings.terminal {
+        viewer = viewer.terminal(columns, rows);
     }
-    if args.hue_rotation != 0 {
-        processing::hue_rotate_img(&mut img, args.hue_rotation);
+    if let Some((columns, rows)) = settings.placement {
+        viewer = viewer.placement(columns, rows);
     }
-    rendering::display(&img, shading).unwrap();
+    viewer
 }
 
 // ======================== Utility ========================
 
-pub fn load_image(path: &str) -> image::RgbaImage {
-    let img = open(path).expect("Failed to open image");
-    img.to_rgba8()
+/// The filters of the flags and `--filter`, in the order they were given.
+/// The black background is removed first by default, unless a stage removes a color.
+fn pipeline(args: &Cli, matches: &ArgMatches) -> Result<Pipeline> {
+    // Position of an option given on the command line or in the configuration
+    let given = |id: &str| match matches.value_source(id) {
+        Some(ValueSource::CommandLine) => matches.index_of(id),
+        _ => None,
+    };
+    let mut stages: Vec<(usize, Box<dyn Filter>)> = Vec::new();
+    if let Some(i) = given("grayscale") {
+        stages.push((i, Box::new(filter::Grayscale)));
+    }
+    if let Some(i) = given("invert") {
+        stages.push((i, Box::new(filter::Invert)));
+    }
+    if let Some(i) = given("brightness") {
+        stages.push((i, Box::new(filter::Brightness(args.brightness))));
+    }
+    if let Some(i) = given("contrast") {
+        stages.push((i, Box::new(filter::Contrast(args.contrast))));
+    }
+    if let Some(i) = given("hue_rotation") {
+        stages.push((i, Box::new(filter::HueRotate(args.hue_rotation))));
+    }
+    if let Some((i, spec)) = given("filter").zip(args.filter.as_deref()) {
+        stages.push((i, Box::new(Pipeline::parse(spec)?)));
+    }
+    let remove_color = parse_rm_color(&args.rm_color)?.map(|color| filter::RemoveColor {
+        color,
+        tolerance: args.rm_tolerance,
+    });
+    match (given("rm_color"), remove_color) {
+        (Some(i), Some(remove_color)) => stages.push((i, Box::new(remove_color))),
+        // Arguments start at 1, after the name of the program
+        (None, Some(remove_color)) if !stages.iter().any(|(_, stage)| stage.removes_color()) => {
+            stages.push((0, Box::new(remove_color)))
+        }
+        _ => {}
+    }
+    stages.sort_by_key(|(i, _)| *i);
+    let mut pipeline = Pipeline::new();
+    for (_, stage) in stages {
+        pipeline.push(stage);
+    }
+    Ok(pipeline)
+}
+
+/// Parse a built-in shade method, a ramp named in the configuration, or else the characters of a ramp
+fn parse_shade_method(
+    shade_method: &str,
+    threshold: &str,
+    ramps: &HashMap<String, String>,
+) -> Result<ShadeMethod> {
+    Ok(match shade_method.to_lowercase().as_str() {
+        "ascii" => ShadeMethod::Ascii,
+        "blocks" => ShadeMethod::Blocks,
+        "half" => ShadeMethod::Half,
+        "quadrant" => ShadeMethod::Quadrant,
+        "sextant" => ShadeMethod::Sextant,
+        "braille" => ShadeMethod::Braille(parse_threshold(threshold)?),
+        "shape" => ShadeMethod::Shape,
+        "" => {
+            return Err(Error::InvalidArgument(
+                "Invalid shade method: empty".to_string(),
+            ))
+        }
+        name if ramps.contains_key(name) => ShadeMethod::Custom(Some(ramps[name].clone())),
+        mapping => ShadeMethod::Custom(Some(mapping.to_string())),
+    })
+}
+
+fn parse_protocol(protocol: &str) -> Result<Protocol> {
+    Ok(match protocol {
+        "text" => Protocol::Text,
+        "sixel" => Protocol::Sixel,
+        "kitty" => Protocol::Kitty,
+        "iterm" | "iterm2" => Protocol::Iterm,
+        protocol => {
+            return Err(Error::InvalidArgument(format!(
+                "Invalid protocol: {}",
+                protocol
+            )))
+        }
+    })
+}
+
+fn parse_colors(colors: &str) -> Result<ColorMode> {
+    Ok(match colors {
+        "truecolor" | "24bit" => ColorMode::TrueColor,
+        "256" => ColorMode::Ansi256,
+        "16" => ColorMode::Ansi16,
+        "none" => ColorMode::None,
+        colors => {
+            return Err(Error::InvalidArgument(format!(
+                "Invalid colors: {}",
+                colors
+            )))
+        }
+    })
+}
+
+fn parse_dither(dither: &str) -> Result<Dither> {
+    Ok(match dither.to_lowercase().as_str() {
+        "none" => Dither::None,
+        "floyd-steinberg" | "fs" => Dither::FloydSteinberg,
+        "atkinson" => Dither::Atkinson,
+        "sierra" => Dither::Sierra,
+        "bayer2" => Dither::Bayer(2),
+        "bayer4" => Dither::Bayer(4),
+        "bayer8" => Dither::Bayer(8),
+        dither => {
+            return Err(Error::InvalidArgument(format!(
+                "Invalid dither: {}",
+                dither
+            )))
+        }
+    })
+}
+
+fn parse_fit(fit: &str) -> Result<sizing::Fit> {
+    Ok(match fit.to_lowercase().as_str() {
+        "contain" => sizing::Fit::Contain,
+        "cover" => sizing::Fit::Cover,
+        "stretch" => sizing::Fit::Stretch,
+        "none" => sizing::Fit::None,
+        fit => return Err(Error::InvalidArgument(format!("Invalid fit: {}", fit))),
+    })
+}
+
+fn parse_resample(resample: &str) -> Result<Resample> {
+    Ok(match resample.to_lowercase().as_str() {
+        "nearest" => Resample::Nearest,
+        "triangle" => Resample::Triangle,
+        "catmull-rom" => Resample::CatmullRom,
+        "gaussian" => Resample::Gaussian,
+        "lanczos3" => Resample::Lanczos3,
+        "area" => Resample::Area,
+        resample => {
+            return Err(Error::InvalidArgument(format!(
+                "Invalid resampling filter: {}",
+                resample
+            )))
+        }
+    })
+}
+
+/// Parse a size in cells like `40x20`
+fn parse_placement(placement: &str) -> Result<(u32, u32)> {
+    let invalid = || Error::InvalidArgument(format!("Invalid placement: {}", placement));
+    let (columns, rows) = placement.split_once(['x', 'X']).ok_or_else(invalid)?;
+    let cells = |cells: &str| {
+        cells
+            .trim()
+            .parse::<u32>()
+            .ok()
+            .filter(|cells| *cells > 0)
+            .ok_or_else(invalid)
+    };
+    Ok((cells(columns)?, cells(rows)?))
+}
+
+fn parse_threshold(threshold: &str) -> Result<Threshold> {
+    Ok(match threshold.to_lowercase().as_str() {
+        "otsu" => Threshold::Otsu,
+        "mean" => Threshold::Mean,
+        value => match value.parse() {
+            Ok(value) => Threshold::Fixed(value),
+            Err(_) => {
+                return Err(Error::InvalidArgument(format!(
+                    "Invalid threshold: {}",
+                    value
+                )))
+            }
+        },
+    })
+}
+
+/// Parse a background removal color like `255,0,0`, where an empty string disables removal
+fn parse_rm_color(rm_color: &str) -> Result<Option<Rgb<u8>>> {
+    if rm_color.is_empty() {
+        return Ok(None);
+    }
+    let invalid =
+        || Error::InvalidArgument(format!("Invalid background removal color: {}", rm_color));
+    let channels = rm_color
+        .split(',')
+        .map(|channel| channel.trim().parse::<u8>().map_err(|_| invalid()))
+        .collect::<Result<Vec<u8>>>()?;
+    match channels[..] {
+        [red, green, blue] => Ok(Some(Rgb([red, green, blue]))),
+        _ => Err(invalid()),
+    }
+}
+
+/// Expand directories to the images in them and globs to the files they match
+fn expand_paths(patterns: &[String]) -> Result<Vec<String>> {
+    let mut paths = Vec::new();
+    for pattern in patterns {
+        let path = Path::new(pattern);
+        if path.is_dir() {
+            let mut images: Vec<String> = std::fs::read_dir(path)
+                .map_err(|source| Error::Io {
+                    path: Some(pattern.clone()),
+                    source,
+                })?
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    path.is_file()
+                        && (ImageFormat::from_path(path).is_ok()
+                            || ansi::is_ansi(&path.to_string_lossy(), &[]))
+                })
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect();
+            images.sort();
+            paths.extend(images);
+        } else if pattern != STDIN && !path.exists() && pattern.contains(['*', '?', '[']) {
+            // Shells on Windows leave globs to the program
+            let matches = glob::glob(pattern)
+                .map_err(|_| Error::InvalidArgument(format!("Invalid glob: {}", pattern)))?;
+            paths.extend(
+                matches
+                    .flatten()
+                    .filter(|path| path.is_file())
+                    .map(|path| path.to_string_lossy().into_owned()),
+            );
+        } else {
+            paths.push(pattern.clone());
+        }
+    }
+    Ok(paths)
 }
@@ -2,31 +2,45 @@
 
 use image::{ImageBuffer, Rgb, Rgba};
 
-use crate::ShadeMethod;
+use crate::{Dither, Error, Resample, Result, ShadeMethod, Threshold};
 
 pub const SHADE_METHOD: &[(ShadeMethod, &str)] = &[
     (ShadeMethod::Ascii, " .-:=+*#%@"),
     (ShadeMethod::Blocks, " ░▒▓█"),
     (ShadeMethod::Half, " ▄▀█"),
+    (ShadeMethod::Quadrant, " ▘▝▀▖▌▞▛▗▚▐▜▄▙▟█"),
+    (ShadeMethod::Sextant, " 🬀🬁🬂🬃🬄🬅🬆🬇🬈🬉🬊🬋🬌🬍🬎▌🬲🬹🬻▐█"),
+    (ShadeMethod::Braille(Threshold::Otsu), "⠀⠁⠃⠇⡇⣇⣧⣷⣿"),
+    (ShadeMethod::Shape, "any printable ASCII, e.g. /\\|_-()<>"),
     (ShadeMethod::Custom(None), "your characters here"),
 ];
 
-pub fn shade(pixel: Rgba<u8>, shade_method: &ShadeMethod) -> char {
-    let shade_ascii = |shade_map: &str| {
-        let gray = grayscale_value(pixel);
-        shade_map
-            .chars()
-            .nth((gray as f32 / 255.0 * (shade_map.len() as f32)) as usize)
-            .unwrap_or(shade_map.chars().last().unwrap())
-    };
+/// The characters of a luminance ramp, from darkest to brightest.
+/// Methods that draw cells from several pixels have no ramp.
+pub fn shade_map(shade_method: &ShadeMethod) -> Result<&str> {
     match shade_method {
-        ShadeMethod::Ascii => shade_ascii(SHADE_METHOD[0].1),
-        ShadeMethod::Blocks => shade_ascii(SHADE_METHOD[1].1),
-        ShadeMethod::Custom(shade_map) => shade_ascii(shade_map.as_ref().unwrap()),
-        _ => panic!("Invalid shade method for single pixel"),
+        ShadeMethod::Ascii => Ok(SHADE_METHOD[0].1),
+        ShadeMethod::Blocks => Ok(SHADE_METHOD[1].1),
+        ShadeMethod::Custom(Some(shade_map)) if !shade_map.is_empty() => Ok(shade_map),
+        shade_method => Err(Error::InvalidArgument(format!(
+            "The {} shade method does not shade single pixels",
+            shade_method
+        ))),
     }
 }
 
+pub fn shade(pixel: Rgba<u8>, shade_method: &ShadeMethod) -> Result<char> {
+    let shade_map: Vec<char> = shade_map(shade_method)?.chars().collect();
+    Ok(shade_ramp(pixel, &shade_map))
+}
+
+/// The character of a non-empty luminance ramp for the luminance of `pixel`
+pub fn shade_ramp(pixel: Rgba<u8>, shade_map: &[char]) -> char {
+    let gray = grayscale_value(pixel);
+    let index = (gray as f32 / 255.0 * (shade_map.len() as f32)) as usize;
+    shade_map[index.min(shade_map.len() - 1)]
+}
+
 pub fn invert(pixel: Rgba<u8>) -> Rgba<u8> {
     Rgba([255 - pixel[0], 255 - pixel[1], 255 - pixel[2], pixel[3]])
 }
@@ -54,15 +68,15 @@ pub fn grayscale_img(img: &mut ImageBuffer<Rgba<u8>, Vec<u8>>) {
 }
 
 pub fn brightness_img(img: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, value: i32) {
-    image::imageops::brighten(img, value);
+    image::imageops::colorops::brighten_in_place(img, value);
 }
 
 pub fn contrast_img(img: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, value: f32) {
-    image::imageops::contrast(img, value);
+    image::imageops::colorops::contrast_in_place(img, value);
 }
 
 pub fn hue_rotate_img(img: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, value: i32) {
-    image::imageops::huerotate(img, value);
+    image::imageops::colorops::huerotate_in_place(img, value);
 }
 
 pub fn rgba_to_rgb(p: Rgba<u8>) -> Rgb<u8> {
@@ -81,6 +95,48 @@ pub fn color_distance(a: Rgb<u8>, b: Rgb<u8>) -> f32 {
     dist.sqrt()
 }
 
+/// Convert an sRGB channel to linear light
+pub fn srgb_to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Convert a linear light channel to sRGB
+pub fn linear_to_srgb(c: f32) -> u8 {
+    let c = if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    };
+    (c * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+/// Convert an sRGB color to CIELAB (D65 white point)
+pub fn rgb_to_lab(color: Rgb<u8>) -> [f32; 3] {
+    let [r, g, b] = color.0.map(srgb_to_linear);
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+    let f = |t: f32| {
+        if t > 0.008856 {
+            t.cbrt()
+        } else {
+            7.787 * t + 16.0 / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// Perceptual distance between two CIELAB colors (CIE76)
+pub fn lab_distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
 pub const TRANSPARENT: Rgba<u8> = Rgba([0, 0, 0, 0]);
 pub fn is_transparent(pixel: Rgba<u8>) -> bool {
     pixel[3] == TRANSPARENT[3]
@@ -98,3 +154,390 @@ pub fn remove_bg_color(
         }
     }
 }
+
+/// The luminance above which a pixel in `values` counts as lit
+pub fn threshold_value(values: &[u8], threshold: Threshold) -> u8 {
+    let (min, max) = values.iter().fold((u8::MAX, u8::MIN), |(min, max), v| {
+        (min.min(*v), max.max(*v))
+    });
+    // A flat cell has nothing to separate, so it is either fully lit or fully dark
+    if values.is_empty() || max - min < 32 {
+        return match threshold {
+            Threshold::Fixed(value) => value,
+            _ => 127,
+        };
+    }
+    match threshold {
+        Threshold::Fixed(value) => value,
+        Threshold::Mean => {
+            (values.iter().map(|v| *v as u32).sum::<u32>() / values.len() as u32) as u8
+        }
+        Threshold::Otsu => otsu_threshold(values),
+    }
+}
+
+/// Otsu's method: the threshold maximizing the between-class variance of `values`
+pub fn otsu_threshold(values: &[u8]) -> u8 {
+    let total = values.len() as f32;
+    let sum: f32 = values.iter().map(|v| *v as f32).sum();
+    let mut best = (0.0, 0);
+    let mut sorted = values.to_vec();
+    sorted.sort_unstable();
+    let mut below_count = 0.0;
+    let mut below_sum = 0.0;
+    for (i, value) in sorted.iter().enumerate() {
+        below_count += 1.0;
+        below_sum += *value as f32;
+        // Only split between distinct values
+        if sorted.get(i + 1).is_none_or(|next| next == value) {
+            continue;
+        }
+        let above_count = total - below_count;
+        let mean_below = below_sum / below_count;
+        let mean_above = (sum - below_sum) / above_count;
+        let variance = below_count * above_count * (mean_below - mean_above).powi(2);
+        if variance > best.0 {
+            best = (variance, *value);
+        }
+    }
+    best.1
+}
+
+/// Error diffusion weights as `(dx, dy, weight)` entries
+type Kernel = &'static [(i32, i32, f32)];
+
+/// The error diffusion kernel of a dithering method and the divisor of its weights
+fn diffusion_kernel(dither: Dither) -> Option<(Kernel, f32)> {
+    match dither {
+        Dither::FloydSteinberg => {
+            Some((&[(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)], 16.0))
+        }
+        // Only 6/8 of the error is diffused, which keeps contrast high
+        Dither::Atkinson => Some((
+            &[
+                (1, 0, 1.0),
+                (2, 0, 1.0),
+                (-1, 1, 1.0),
+                (0, 1, 1.0),
+                (1, 1, 1.0),
+                (0, 2, 1.0),
+            ],
+            8.0,
+        )),
+        Dither::Sierra => Some((
+            &[
+                (1, 0, 5.0),
+                (2, 0, 3.0),
+                (-2, 1, 2.0),
+                (-1, 1, 4.0),
+                (0, 1, 5.0),
+                (1, 1, 4.0),
+                (2, 1, 2.0),
+                (-1, 2, 2.0),
+                (0, 2, 3.0),
+                (1, 2, 2.0),
+            ],
+            32.0,
+        )),
+        _ => None,
+    }
+}
+
+/// The ordered dithering threshold of a pixel in a `size` x `size` Bayer matrix, in `-0.5..0.5`
+pub fn bayer_threshold(x: u32, y: u32, size: u32) -> f32 {
+    let bits = size.trailing_zeros();
+    let mut value = 0;
+    for bit in 0..bits {
+        let (xb, yb) = ((x >> bit) & 1, (y >> bit) & 1);
+        value = (value << 2) | ((xb ^ yb) << 1) | yb;
+    }
+    (value as f32 + 0.5) / (size * size) as f32 - 0.5
+}
+
+/// Quantize every opaque pixel to one of `levels` while dithering, returning the chosen level indices.
+/// `value` gives the channels of a pixel, and `nearest` the index of the level closest to some channels.
+/// Ordered dithering offsets the channels by up to half of `spread` before quantizing.
+pub fn dither_indices<const C: usize>(
+    img: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    dither: Dither,
+    spread: f32,
+    levels: &[[f32; C]],
+    value: impl Fn(Rgba<u8>) -> [f32; C],
+    nearest: impl Fn([f32; C]) -> usize,
+) -> Vec<Option<usize>> {
+    let (width, height) = img.dimensions();
+    let mut values: Vec<[f32; C]> = img.pixels().map(|p| value(*p)).collect();
+    let mut indices = vec![None; values.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let i = (y * width + x) as usize;
+            if is_transparent(*img.get_pixel(x, y)) {
+                continue;
+            }
+            let current = values[i];
+            let index = match dither {
+                Dither::Bayer(size) => {
+                    let offset = bayer_threshold(x % size, y % size, size) * spread;
+                    nearest(current.map(|c| c + offset))
+                }
+                _ => nearest(current),
+            };
+            indices[i] = Some(index);
+            let Some((kernel, divisor)) = diffusion_kernel(dither) else {
+                continue;
+            };
+            for (dx, dy, weight) in kernel {
+                let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+                if nx < 0 || nx >= width as i32 || ny >= height as i32 {
+                    continue;
+                }
+                let neighbour = &mut values[(ny as u32 * width + nx as u32) as usize];
+                for c in 0..C {
+                    neighbour[c] += (current[c] - levels[index][c]) * weight / divisor;
+                }
+            }
+        }
+    }
+    indices
+}
+
+/// Dither the image to the luminance levels of a ramp with `count` characters
+pub fn dither_ramp(
+    img: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    count: usize,
+    dither: Dither,
+) -> Vec<Option<usize>> {
+    let step = 255.0 / (count.max(2) - 1) as f32;
+    let levels: Vec<[f32; 1]> = (0..count).map(|i| [i as f32 * step]).collect();
+    dither_indices(
+        img,
+        dither,
+        step,
+        &levels,
+        |p| [grayscale_value(p) as f32],
+        |[v]| ((v / step).round().max(0.0) as usize).min(count - 1),
+    )
+}
+
+/// Dither the colors of the image to the given palette, matching colors perceptually
+pub fn dither_palette(
+    img: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+    palette: &[Rgb<u8>],
+    dither: Dither,
+) {
+    let levels: Vec<[f32; 3]> = palette.iter().map(|c| c.0.map(|c| c as f32)).collect();
+    let labs: Vec<[f32; 3]> = palette.iter().map(|c| rgb_to_lab(*c)).collect();
+    // Roughly the distance between neighbouring palette colors on each channel
+    let spread = 255.0 / (palette.len() as f32).cbrt();
+    let indices = dither_indices(
+        img,
+        dither,
+        spread,
+        &levels,
+        |p| rgba_to_rgb(p).0.map(|c| c as f32),
+        |rgb| {
+            let lab = rgb_to_lab(Rgb(rgb.map(|c| c.round().clamp(0.0, 255.0) as u8)));
+            (0..labs.len())
+                .min_by(|a, b| lab_distance(labs[*a], lab).total_cmp(&lab_distance(labs[*b], lab)))
+                .unwrap()
+        },
+    );
+    for (pixel, index) in img.pixels_mut().zip(indices) {
+        if let Some(index) = index {
+            let Rgb([r, g, b]) = palette[index];
+            *pixel = Rgba([r, g, b, 255]);
+        }
+    }
+}
+
+// ======================== Resampling ========================
+
+/// Resize the image to `width` x `height` pixels
+pub fn resize(
+    img: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    width: u32,
+    height: u32,
+    resample: Resample,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    use image::imageops::{self, FilterType};
+    let filter = match resample {
+        Resample::Nearest => FilterType::Nearest,
+        Resample::Triangle => FilterType::Triangle,
+        Resample::CatmullRom => FilterType::CatmullRom,
+        Resample::Gaussian => FilterType::Gaussian,
+        Resample::Lanczos3 => FilterType::Lanczos3,
+        Resample::Area => return area_resize(img, width, height),
+    };
+    imageops::resize(img, width, height, filter)
+}
+
+/// The source pixels covered by each of `to` destination pixels, with the fraction of each
+fn area_weights(from: u32, to: u32) -> Vec<Vec<(usize, f32)>> {
+    let step = from as f32 / to as f32;
+    (0..to)
+        .map(|i| {
+            let (start, end) = (i as f32 * step, (i + 1) as f32 * step);
+            (start.floor() as u32..(end.ceil() as u32).min(from))
+                .map(|j| {
+                    let overlap = end.min(j as f32 + 1.0) - start.max(j as f32);
+                    (j as usize, overlap / step)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Resize by averaging all source pixels each destination pixel covers.
+/// Colors are averaged in linear light and weighted by alpha, so edges neither darken nor bleed.
+/// Pixels at least half covered are opaque and the others transparent, as partial alpha
+/// darkens text colors into a halo around transparent regions.
+pub fn area_resize(
+    img: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    width: u32,
+    height: u32,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let (source_width, source_height) = img.dimensions();
+    if width == 0 || height == 0 || source_width == 0 || source_height == 0 {
+        return ImageBuffer::new(width, height);
+    }
+    let linear: Vec<[f32; 4]> = img
+        .pixels()
+        .map(|p| {
+            let alpha = p[3] as f32 / 255.0;
+            [
+                srgb_to_linear(p[0]) * alpha,
+                srgb_to_linear(p[1]) * alpha,
+                srgb_to_linear(p[2]) * alpha,
+                alpha,
+            ]
+        })
+        .collect();
+    // Average the columns of every source row first, then the rows of every column
+    let columns = area_weights(source_width, width);
+    let mut rows_averaged = vec![[0.0; 4]; (width * source_height) as usize];
+    for y in 0..source_height as usize {
+        let row = &linear[y * source_width as usize..][..source_width as usize];
+        for (x, weights) in columns.iter().enumerate() {
+            let sum = &mut rows_averaged[y * width as usize + x];
+            for (j, weight) in weights {
+                for (sum, value) in sum.iter_mut().zip(row[*j]) {
+                    *sum += value * weight;
+                }
+            }
+        }
+    }
+    let rows = area_weights(source_height, height);
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let mut sum = [0.0; 4];
+        for (j, weight) in &rows[y as usize] {
+            let pixel = rows_averaged[j * width as usize + x as usize];
+            for (sum, value) in sum.iter_mut().zip(pixel) {
+                *sum += value * weight;
+            }
+        }
+        let alpha = sum[3];
+        if alpha < 0.5 {
+            return TRANSPARENT;
+        }
+        Rgba([
+            linear_to_srgb(sum[0] / alpha),
+            linear_to_srgb(sum[1] / alpha),
+            linear_to_srgb(sum[2] / alpha),
+            255,
+        ])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn area_resize_edges() {
+        // A white square with edges that fall inside the resized pixels
+        let white = Rgba([255, 255, 255, 255]);
+        let mut img = ImageBuffer::from_pixel(16, 16, TRANSPARENT);
+        for (x, y) in (3..13).flat_map(|x| (3..13).map(move |y| (x, y))) {
+            img.put_pixel(x, y, white);
+        }
+        let resized = area_resize(&img, 5, 5);
+        assert!(resized.pixels().any(|p| *p == white));
+        assert!(resized.pixels().any(|p| is_transparent(*p)));
+        for pixel in resized.pixels().filter(|p| !is_transparent(**p)) {
+            assert_eq!(rgba_to_rgb(*pixel), Rgb([255, 255, 255]));
+        }
+    }
+
+    #[test]
+    fn bayer_matrix() {
+        let rank = |x, y| ((bayer_threshold(x, y, 2) + 0.5) * 4.0 - 0.5).round() as u32;
+        assert_eq!(
+            [[rank(0, 0), rank(1, 0)], [rank(0, 1), rank(1, 1)]],
+            [[0, 2], [3, 1]]
+        );
+        let mut ranks: Vec<u32> = (0..16)
+            .map(|i| ((bayer_threshold(i % 4, i / 4, 4) + 0.5) * 16.0 - 0.5).round() as u32)
+            .collect();
+        ranks.sort();
+        assert_eq!(ranks, (0..16).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn ramp_levels() {
+        // Levels are counted in characters, the blocks are three bytes each
+        let gray = |value| shade(Rgba([value, value, value, 255]), &ShadeMethod::Blocks).unwrap();
+        assert_eq!(
+            [gray(0), gray(60), gray(128), gray(200), gray(255)],
+            [' ', '░', '▒', '▓', '█']
+        );
+    }
+
+    #[test]
+    fn ramp_dithering() {
+        let mut img = ImageBuffer::from_pixel(4, 2, Rgba([128, 128, 128, 255]));
+        img.put_pixel(3, 1, Rgba([0, 0, 0, 0]));
+        let levels = |dither| dither_ramp(&img, 2, dither);
+        assert_eq!(
+            levels(Dither::FloydSteinberg)[..4],
+            [Some(1), Some(0), Some(1), Some(0)]
+        );
+        // Mid gray lights the cells of the upper half of the matrix
+        assert_eq!(
+            levels(Dither::Bayer(2)),
+            [
+                Some(0),
+                Some(1),
+                Some(0),
+                Some(1),
+                Some(1),
+                Some(0),
+                Some(1),
+                None
+            ]
+        );
+        assert_eq!(
+            levels(Dither::None),
+            vec![Some(1); 7]
+                .into_iter()
+                .chain([None])
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn atkinson_gradient() {
+        let mut img = ImageBuffer::new(4, 1);
+        for (x, value) in [64, 128, 128, 192].into_iter().enumerate() {
+            img.put_pixel(x as u32, 0, Rgba([value, value, value, 255]));
+        }
+        // The error of the bright second pixel darkens the third
+        assert_eq!(
+            dither_ramp(&img, 2, Dither::Atkinson),
+            [Some(0), Some(1), Some(0), Some(1)]
+        );
+        assert_eq!(
+            dither_ramp(&img, 2, Dither::None),
+            [Some(0), Some(1), Some(1), Some(1)]
+        );
+    }
+}
@@ -0,0 +1,266 @@
+// ======================== Renderers ========================
+
+use std::io::Write;
+
+use image::RgbaImage;
+
+use crate::{
+    frame::CellGrid, processing::SHADE_METHOD, rendering, ColorMode, Dither, Error, Result,
+    ShadeMethod, Threshold,
+};
+
+/// Draws images as character cells, each showing a block of pixels
+pub trait Renderer {
+    /// Pixels drawn in a single cell, horizontally and vertically
+    fn resolution(&self) -> (u32, u32);
+
+    /// Cells an image of `width` by `height` pixels is drawn in
+    fn cells(&self, width: u32, height: u32) -> (u32, u32) {
+        let (x, y) = self.resolution();
+        (width / x, height / y)
+    }
+
+    /// Render an image into a grid of cells, leftover pixels beyond the last full cell are dropped
+    fn render_cells(&self, img: &RgbaImage) -> Result<CellGrid>;
+
+    /// Write an image as lines of text, ending on the line below it
+    fn render(&self, img: &RgbaImage, out: &mut dyn Write) -> Result<()> {
+        Ok(self.render_cells(img)?.write(out, "\n")?)
+    }
+}
+
+/// Settings of the built-in renderers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Options {
+    pub colors: ColorMode,
+    pub dither: Dither,
+    /// Dot threshold of braille
+    pub threshold: Threshold,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            colors: ColorMode::TrueColor,
+            dither: Dither::None,
+            threshold: Threshold::Otsu,
+        }
+    }
+}
+
+impl Options {
+    /// Draw into a new grid, after dithering the image to the palette of the colors
+    fn draw(
+        &self,
+        img: &RgbaImage,
+        draw: impl FnOnce(&mut CellGrid, &RgbaImage) -> Result<()>,
+    ) -> Result<CellGrid> {
+        let img = rendering::dither_to_palette(img, self.colors, self.dither);
+        let mut grid = CellGrid::new(self.colors);
+        draw(&mut grid, &img)?;
+        Ok(grid)
+    }
+}
+
+/// A character per pixel from a luminance ramp, ordered from darkest to brightest
+#[derive(Debug, Clone)]
+pub struct Ramp {
+    chars: Vec<char>,
+    options: Options,
+}
+
+impl Ramp {
+    pub fn new(chars: &str, options: Options) -> Self {
+        Self {
+            chars: chars.chars().collect(),
+            options,
+        }
+    }
+}
+
+impl Renderer for Ramp {
+    fn resolution(&self) -> (u32, u32) {
+        (1, 1)
+    }
+
+    fn render_cells(&self, img: &RgbaImage) -> Result<CellGrid> {
+        if self.chars.is_empty() {
+            return Err(Error::InvalidArgument(
+                "A luminance ramp needs at least one character".to_string(),
+            ));
+        }
+        self.options.draw(img, |grid, img| {
+            rendering::display_stream_simple(grid, img, &self.chars, self.options.dither);
+            Ok(())
+        })
+    }
+}
+
+/// Two pixels per cell with upper and lower half blocks
+#[derive(Debug, Clone, Copy)]
+pub struct Half(pub Options);
+
+impl Renderer for Half {
+    fn resolution(&self) -> (u32, u32) {
+        (1, 2)
+    }
+
+    fn render_cells(&self, img: &RgbaImage) -> Result<CellGrid> {
+        self.0.draw(img, |grid, img| {
+            rendering::display_stream_half(grid, img);
+            Ok(())
+        })
+    }
+}
+
+/// 2x2 pixels per cell with quadrant blocks
+#[derive(Debug, Clone, Copy)]
+pub struct Quadrant(pub Options);
+
+impl Renderer for Quadrant {
+    fn resolution(&self) -> (u32, u32) {
+        (2, 2)
+    }
+
+    fn render_cells(&self, img: &RgbaImage) -> Result<CellGrid> {
+        self.0.draw(img, |grid, img| {
+            rendering::display_stream_two_color(grid, img, 2, rendering::quadrant_glyph);
+            Ok(())
+        })
+    }
+}
+
+/// 2x3 pixels per cell with the sextants of Unicode 13
+#[derive(Debug, Clone, Copy)]
+pub struct Sextant(pub Options);
+
+impl Renderer for Sextant {
+    fn resolution(&self) -> (u32, u32) {
+        (2, 3)
+    }
+
+    fn render_cells(&self, img: &RgbaImage) -> Result<CellGrid> {
+        self.0.draw(img, |grid, img| {
+            rendering::display_stream_two_color(grid, img, 3, rendering::sextant_glyph);
+            Ok(())
+        })
+    }
+}
+
+/// 2x4 pixels per cell with braille dots, lit by the threshold of the options
+#[derive(Debug, Clone, Copy)]
+pub struct Braille(pub Options);
+
+impl Renderer for Braille {
+    fn resolution(&self) -> (u32, u32) {
+        (2, 4)
+    }
+
+    fn render_cells(&self, img: &RgbaImage) -> Result<CellGrid> {
+        self.0.draw(img, |grid, img| {
+            rendering::display_stream_braille(grid, img, self.0.threshold);
+            Ok(())
+        })
+    }
+}
+
+/// A glyph of the bundled font per cell, matched to the shape of its pixels
+#[derive(Debug, Clone, Copy)]
+pub struct Shape(pub Options);
+
+impl Renderer for Shape {
+    fn resolution(&self) -> (u32, u32) {
+        (crate::font::GLYPH_WIDTH, crate::font::GLYPH_HEIGHT)
+    }
+
+    fn render_cells(&self, img: &RgbaImage) -> Result<CellGrid> {
+        self.0.draw(img, |grid, img| {
+            rendering::display_stream_shape(grid, img);
+            Ok(())
+        })
+    }
+}
+
+impl ShadeMethod {
+    /// The built-in renderer drawing with this shade method
+    pub fn renderer(&self, options: Options) -> Result<Box<dyn Renderer>> {
+        Ok(match self {
+            ShadeMethod::Ascii => Box::new(Ramp::new(SHADE_METHOD[0].1, options)),
+            ShadeMethod::Blocks => Box::new(Ramp::new(SHADE_METHOD[1].1, options)),
+            ShadeMethod::Half => Box::new(Half(options)),
+            ShadeMethod::Quadrant => Box::new(Quadrant(options)),
+            ShadeMethod::Sextant => Box::new(Sextant(options)),
+            ShadeMethod::Braille(threshold) => Box::new(Braille(Options {
+                threshold: *threshold,
+                ..options
+            })),
+            ShadeMethod::Shape => Box::new(Shape(options)),
+            ShadeMethod::Custom(Some(chars)) => Box::new(Ramp::new(chars, options)),
+            ShadeMethod::Custom(None) => {
+                return Err(Error::InvalidArgument(
+                    "The custom shade method needs characters".to_string(),
+                ))
+            }
+        })
+    }
+}
+
+/// Creates a renderer with the given options
+pub type Factory = Box<dyn Fn(Options) -> Box<dyn Renderer>>;
+
+/// Renderers by the name they are selected with
+pub struct Registry {
+    factories: Vec<(String, Factory)>,
+}
+
+impl Registry {
+    /// The built-in renderers, named after their shade methods
+    pub fn new() -> Self {
+        let mut registry = Self {
+            factories: Vec::new(),
+        };
+        registry.register("ascii", |options| {
+            Box::new(Ramp::new(SHADE_METHOD[0].1, options))
+        });
+        registry.register("blocks", |options| {
+            Box::new(Ramp::new(SHADE_METHOD[1].1, options))
+        });
+        registry.register("half", |options| Box::new(Half(options)));
+        registry.register("quadrant", |options| Box::new(Quadrant(options)));
+        registry.register("sextant", |options| Box::new(Sextant(options)));
+        registry.register("braille", |options| Box::new(Braille(options)));
+        registry.register("shape", |options| Box::new(Shape(options)));
+        registry
+    }
+
+    /// Add a renderer, replacing any registered under the same name
+    pub fn register(
+        &mut self,
+        name: &str,
+        factory: impl Fn(Options) -> Box<dyn Renderer> + 'static,
+    ) {
+        let name = name.to_lowercase();
+        self.factories.retain(|(existing, _)| *existing != name);
+        self.factories.push((name, Box::new(factory)));
+    }
+
+    /// Create the renderer registered under `name`, ignoring case
+    pub fn create(&self, name: &str, options: Options) -> Option<Box<dyn Renderer>> {
+        let name = name.to_lowercase();
+        self.factories
+            .iter()
+            .find(|(existing, _)| *existing == name)
+            .map(|(_, factory)| factory(options))
+    }
+
+    /// Names of all registered renderers, in the order they were added
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.factories.iter().map(|(name, _)| name.as_str())
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
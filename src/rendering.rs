@@ -1,135 +1,202 @@
-use std::fmt::Display;
+use std::{borrow::Cow, collections::HashMap};
 
-use crossterm::style::{self, Color};
+use base64::Engine;
+use crossterm::style::Color;
 use image::{ImageBuffer, Rgb, Rgba};
+use lazy_static::lazy_static;
 
 use crate::{
-    processing::{self, color_distance, is_transparent, rgba_to_rgb},
-    ShadeMethod,
+    font::{self, GLYPH_HEIGHT, GLYPH_WIDTH},
+    frame::CellGrid,
+    processing::{self, color_distance, grayscale_value, is_transparent, rgba_to_rgb},
+    renderer::Options,
+    ColorMode, Dither, Protocol, Result, ShadeMethod, Threshold,
 };
 
+/// Size and stacking order of a graphics placement, measured in character cells
+#[derive(Debug, Clone, Copy)]
+pub struct Placement {
+    pub columns: u32,
+    pub rows: u32,
+    pub z_index: i32,
+    /// Id of the kitty image, so that it can be deleted before drawing the next frame
+    pub image_id: Option<u32>,
+}
+
 pub fn display(
+    out: &mut dyn std::io::Write,
     img: &ImageBuffer<Rgba<u8>, Vec<u8>>,
     shading: ShadeMethod,
-) -> Result<(), std::io::Error> {
-    let mut out = std::io::stdout();
-    match shading {
-        ShadeMethod::Half => display_stream_half(&mut out, img),
-        _ => display_stream_simple(&mut out, img, shading),
+    protocol: Protocol,
+    placement: Placement,
+    colors: ColorMode,
+    dither: Dither,
+) -> Result<()> {
+    match protocol {
+        Protocol::Sixel => display_stream_sixel(out, img)?,
+        Protocol::Kitty => display_stream_kitty(out, img, placement)?,
+        Protocol::Iterm => display_stream_iterm(out, img, placement)?,
+        Protocol::Text => render_cells(img, shading, colors, dither)?.write(out, "\n")?,
     }
+    Ok(())
 }
 
-fn image_to_crossterm_color(pixel: Rgb<u8>) -> Color {
-    Color::Rgb {
-        r: pixel[0],
-        g: pixel[1],
-        b: pixel[2],
-    }
+/// Render the image to character cells with the given shading method
+pub fn render_cells(
+    img: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    shading: ShadeMethod,
+    colors: ColorMode,
+    dither: Dither,
+) -> Result<CellGrid> {
+    let options = Options {
+        colors,
+        dither,
+        ..Options::default()
+    };
+    shading.renderer(options)?.render_cells(img)
 }
 
-fn print_stream(
-    out: &mut dyn std::io::Write,
-    chr: char,
-    color: Rgb<u8>,
-    bg_color: Option<Rgb<u8>>,
-) -> Result<(), std::io::Error> {
-    if let Some(bg_color) = bg_color {
-        write!(
-            out,
-            "{}",
-            style::SetBackgroundColor(image_to_crossterm_color(bg_color))
-        )?;
+/// Dither the image to the palette of a reduced color mode, so its colors map to themselves
+pub(crate) fn dither_to_palette(
+    img: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    colors: ColorMode,
+    dither: Dither,
+) -> Cow<'_, ImageBuffer<Rgba<u8>, Vec<u8>>> {
+    match palette(colors) {
+        Some(palette) if dither != Dither::None => {
+            let mut img = img.clone();
+            processing::dither_palette(&mut img, &palette, dither);
+            Cow::Owned(img)
+        }
+        _ => Cow::Borrowed(img),
     }
-    write!(
-        out,
-        "{}{}{}",
-        style::SetForegroundColor(image_to_crossterm_color(color)),
-        chr,
-        style::ResetColor
-    )
 }
 
-struct LineRenderer {
-    buffer: Vec<char>,
-    current_color: Option<Rgb<u8>>,
-    current_bg_color: Option<Rgb<u8>>,
+// ======================== Palettes ========================
+
+/// Default RGB values of the 16 ANSI colors in xterm, in color index order
+const ANSI_16: [[u8; 3]; 16] = [
+    [0, 0, 0],
+    [205, 0, 0],
+    [0, 205, 0],
+    [205, 205, 0],
+    [0, 0, 238],
+    [205, 0, 205],
+    [0, 205, 205],
+    [229, 229, 229],
+    [127, 127, 127],
+    [255, 0, 0],
+    [0, 255, 0],
+    [255, 255, 0],
+    [92, 92, 255],
+    [255, 0, 255],
+    [0, 255, 255],
+    [255, 255, 255],
+];
+
+/// Channel levels of the 6x6x6 color cube of the xterm 256 color palette
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+lazy_static! {
+    /// The colors of `ANSI_16` in CIELAB
+    static ref ANSI_16_LAB: Vec<(Color, [f32; 3])> = ANSI_16
+        .iter()
+        .enumerate()
+        .map(|(i, rgb)| (Color::AnsiValue(i as u8), processing::rgb_to_lab(Rgb(*rgb))))
+        .collect();
+    /// The color cube and grayscale ramp (indices 16 to 255) of the xterm 256 color palette in CIELAB.
+    /// The first 16 colors are left out as terminals commonly redefine them.
+    static ref ANSI_256_LAB: Vec<(Color, [f32; 3])> = (16..=255u8)
+        .map(|i| (Color::AnsiValue(i), processing::rgb_to_lab(ansi_256_rgb(i))))
+        .collect();
 }
 
-impl LineRenderer {
-    fn new() -> Self {
-        Self {
-            buffer: Vec::new(),
-            current_color: None,
-            current_bg_color: None,
-        }
+/// The RGB value of a color cube or grayscale ramp entry in the xterm 256 color palette
+fn ansi_256_rgb(index: u8) -> Rgb<u8> {
+    if index >= 232 {
+        let gray = 8 + (index - 232) * 10;
+        Rgb([gray, gray, gray])
+    } else {
+        let i = (index - 16) as usize;
+        Rgb([
+            CUBE_LEVELS[i / 36],
+            CUBE_LEVELS[i / 6 % 6],
+            CUBE_LEVELS[i % 6],
+        ])
     }
+}
 
-    fn clear(&mut self) {
-        self.buffer.clear();
-        self.current_color = None;
-        self.current_bg_color = None;
+/// The RGB values of the palette of a reduced color mode
+fn palette(colors: ColorMode) -> Option<Vec<Rgb<u8>>> {
+    match colors {
+        ColorMode::Ansi256 => Some((16..=255).map(ansi_256_rgb).collect()),
+        ColorMode::Ansi16 => Some(ANSI_16.iter().map(|rgb| Rgb(*rgb)).collect()),
+        _ => None,
     }
+}
 
-    fn display(&mut self, item: &dyn Display) {
-        self.buffer
-            .append(format!("{}", item).chars().collect::<Vec<char>>().as_mut());
-    }
+/// The palette color perceptually closest to `pixel`
+fn nearest_color(palette: &[(Color, [f32; 3])], pixel: Rgb<u8>) -> Color {
+    let lab = processing::rgb_to_lab(pixel);
+    palette
+        .iter()
+        .min_by(|a, b| {
+            processing::lab_distance(a.1, lab).total_cmp(&processing::lab_distance(b.1, lab))
+        })
+        .unwrap()
+        .0
+}
 
-    fn add(&mut self, chr: char, color: Option<Rgb<u8>>, bg_color: Option<Rgb<u8>>) {
-        if (self.current_color != color || self.current_bg_color != bg_color)
-            && !self.buffer.is_empty()
-        {
-            self.display(&style::ResetColor);
-        }
-        if color.is_some() && self.current_color != color {
-            self.display(&style::SetForegroundColor(image_to_crossterm_color(
-                color.unwrap(),
-            )));
-            self.current_color = color;
-        }
-        if bg_color.is_some() && self.current_bg_color != bg_color {
-            self.display(&style::SetBackgroundColor(image_to_crossterm_color(
-                bg_color.unwrap(),
-            )));
-            self.current_bg_color = bg_color;
-        }
-        self.buffer.push(chr);
+/// The RGB value of a terminal color, taking palette colors from the xterm defaults
+pub fn crossterm_to_rgb(color: Color) -> Option<Rgb<u8>> {
+    match color {
+        Color::Rgb { r, g, b } => Some(Rgb([r, g, b])),
+        Color::AnsiValue(i) if i < 16 => Some(Rgb(ANSI_16[i as usize])),
+        Color::AnsiValue(i) => Some(ansi_256_rgb(i)),
+        _ => None,
     }
+}
 
-    fn build(&mut self) -> String {
-        self.display(&style::ResetColor);
-        self.buffer.iter().collect()
+/// Map an image color to a terminal color supported by the color mode
+pub fn image_to_crossterm_color(pixel: Rgb<u8>, colors: ColorMode) -> Option<Color> {
+    match colors {
+        ColorMode::TrueColor => Some(Color::Rgb {
+            r: pixel[0],
+            g: pixel[1],
+            b: pixel[2],
+        }),
+        ColorMode::Ansi256 => Some(nearest_color(&ANSI_256_LAB, pixel)),
+        ColorMode::Ansi16 => Some(nearest_color(&ANSI_16_LAB, pixel)),
+        ColorMode::None => None,
     }
 }
 
-fn display_stream_simple(
-    out: &mut dyn std::io::Write,
+/// Display every pixel as a character of the luminance ramp `shade_map`, which must not be empty
+pub(crate) fn display_stream_simple(
+    grid: &mut CellGrid,
     img: &ImageBuffer<Rgba<u8>, Vec<u8>>,
-    shading: ShadeMethod,
-) -> Result<(), std::io::Error> {
+    shade_map: &[char],
+    dither: Dither,
+) {
     let (width, height) = img.dimensions();
-    let mut renderer = LineRenderer::new();
+    let ramp =
+        (dither != Dither::None).then(|| processing::dither_ramp(img, shade_map.len(), dither));
     for y in 0..height {
         for x in 0..width {
             let pixel = *img.get_pixel(x, y);
-            let chr = processing::shade(pixel, &shading);
-            // print_stream(out, chr, rgba_to_rgb(pixel), None)?;
-            renderer.add(chr, Some(rgba_to_rgb(pixel)), None);
+            let chr = match &ramp {
+                Some(ramp) => ramp[(y * width + x) as usize].map_or(shade_map[0], |i| shade_map[i]),
+                None => processing::shade_ramp(pixel, shade_map),
+            };
+            grid.add(chr, Some(rgba_to_rgb(pixel)), None);
         }
-        // writeln!(out)?;
-        writeln!(out, "{}", renderer.build())?;
-        renderer.clear();
+        grid.end_line();
     }
-    Ok(())
 }
 
 /// Display the image in high resolution by performing subpixel rendering
-fn display_stream_half(
-    out: &mut dyn std::io::Write,
-    img: &ImageBuffer<Rgba<u8>, Vec<u8>>,
-) -> Result<(), std::io::Error> {
+pub(crate) fn display_stream_half(grid: &mut CellGrid, img: &ImageBuffer<Rgba<u8>, Vec<u8>>) {
     let (width, height) = img.dimensions();
-    let mut renderer = LineRenderer::new();
     for y in 0..(height / 2) {
         for x in 0..width {
             let upper = *img.get_pixel(x, y * 2);
@@ -151,12 +218,538 @@ fn display_stream_half(
                     ('▀', upper, Some(lower))
                 }
             };
-            // print_stream(out, chr, color, bg_color)?;
-            renderer.add(chr, Some(color), bg_color);
+            grid.add(chr, Some(color), bg_color);
+        }
+        grid.end_line();
+    }
+}
+
+/// Quadrant block characters indexed by a mask of lit sub-pixels,
+/// bit 0 is the upper left and bit 3 the lower right quadrant
+const QUADRANTS: [char; 16] = [
+    ' ', '▘', '▝', '▀', '▖', '▌', '▞', '▛', '▗', '▚', '▐', '▜', '▄', '▙', '▟', '█',
+];
+
+pub(crate) fn quadrant_glyph(mask: u32) -> char {
+    QUADRANTS[mask as usize]
+}
+
+/// Sextant character for a mask of lit sub-pixels, bit 0 is the upper left and bit 5 the lower right.
+/// The Unicode 13 legacy computing block skips the masks already covered by other block characters.
+pub(crate) fn sextant_glyph(mask: u32) -> char {
+    match mask {
+        0 => ' ',
+        0b010101 => '▌',
+        0b101010 => '▐',
+        0b111111 => '█',
+        _ => {
+            let skipped = (mask > 0b010101) as u32 + (mask > 0b101010) as u32;
+            char::from_u32(0x1FB00 + mask - 1 - skipped).unwrap()
+        }
+    }
+}
+
+/// The rows of a block character split into 2 x `rows` sub-pixels and the mask of its lit ones,
+/// the inverse of `quadrant_glyph` and `sextant_glyph`. Half blocks are quadrants with both halves lit.
+pub(crate) fn block_mask(chr: char) -> Option<(u32, u32)> {
+    if let Some(mask) = QUADRANTS.iter().position(|glyph| *glyph == chr) {
+        return Some((2, mask as u32));
+    }
+    let index = (chr as u32).checked_sub(0x1FB00).filter(|i| *i < 60)? + 1;
+    // Undo the skipping of masks covered by other block characters
+    let index = if index >= 0b010101 { index + 1 } else { index };
+    let index = if index >= 0b101010 { index + 1 } else { index };
+    Some((3, index))
+}
+
+/// Display the image using 2 x `rows` sub-pixel block characters with a foreground and background color.
+/// For every cell, the glyph and color pair with the least color error over its sub-pixels is chosen.
+pub(crate) fn display_stream_two_color(
+    grid: &mut CellGrid,
+    img: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    rows: u32,
+    glyph: fn(u32) -> char,
+) {
+    let (width, height) = img.dimensions();
+    for y in 0..(height / rows) {
+        for x in 0..(width / 2) {
+            let pixels: Vec<Rgba<u8>> = (0..rows * 2)
+                .map(|i| *img.get_pixel(x * 2 + i % 2, y * rows + i / 2))
+                .collect();
+            let (chr, color, bg_color) = fit_two_color(&pixels, glyph);
+            grid.add(chr, color, bg_color);
+        }
+        grid.end_line();
+    }
+}
+
+/// Find the glyph mask and color pair that best approximates the sub-pixels of a cell
+fn fit_two_color(
+    pixels: &[Rgba<u8>],
+    glyph: fn(u32) -> char,
+) -> (char, Option<Rgb<u8>>, Option<Rgb<u8>>) {
+    let full = (1 << pixels.len()) - 1;
+    let opaque = pixels
+        .iter()
+        .enumerate()
+        .filter(|(_, p)| !is_transparent(**p))
+        .fold(0, |mask, (i, _)| mask | 1 << i);
+    let pick = |mask: u32| {
+        pixels
+            .iter()
+            .enumerate()
+            .filter(move |(i, _)| mask & (1 << i) != 0)
+            .map(|(_, p)| *p)
+    };
+    if opaque == 0 {
+        return (' ', None, None);
+    }
+    // Transparent sub-pixels must show the terminal background
+    if opaque != full {
+        return (glyph(opaque), Some(average_color(pick(opaque))), None);
+    }
+    let colors: Vec<Rgb<u8>> = pixels.iter().map(|p| rgba_to_rgb(*p)).collect();
+    let error = |mask: u32, color: Rgb<u8>| -> f32 {
+        colors
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| mask & (1 << i) != 0)
+            .map(|(_, c)| color_distance(*c, color).powi(2))
+            .sum()
+    };
+    let solid = average_color(pick(full));
+    let mut best = (error(full, solid), full, solid, None);
+    // Masks and their complements are equivalent, so only those including the first sub-pixel are tried
+    for mask in (1..full).filter(|mask| mask & 1 != 0) {
+        let fg = average_color(pick(mask));
+        let bg = average_color(pick(full & !mask));
+        let total = error(mask, fg) + error(full & !mask, bg);
+        if total < best.0 {
+            best = (total, mask, fg, Some(bg));
+        }
+    }
+    let (_, mask, fg, bg) = best;
+    (glyph(mask), Some(fg), bg)
+}
+
+/// A glyph or image cell as blurred intensities in `0..=1`, with precomputed statistics
+struct GlyphPatch {
+    values: Vec<f32>,
+    mean: f32,
+    variance: f32,
+}
+
+impl GlyphPatch {
+    fn new(values: Vec<f32>) -> Self {
+        // Blurring makes the comparison tolerant to strokes being off by a pixel
+        let values = box_blur(&values, GLYPH_WIDTH as usize, GLYPH_HEIGHT as usize);
+        let mean = values.iter().sum::<f32>() / values.len() as f32;
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / values.len() as f32;
+        Self {
+            values,
+            mean,
+            variance,
+        }
+    }
+
+    /// Structural similarity (SSIM) over the whole patch
+    fn similarity(&self, other: &GlyphPatch) -> f32 {
+        const C1: f32 = 0.01 * 0.01;
+        const C2: f32 = 0.03 * 0.03;
+        let covariance = self
+            .values
+            .iter()
+            .zip(&other.values)
+            .map(|(a, b)| (a - self.mean) * (b - other.mean))
+            .sum::<f32>()
+            / self.values.len() as f32;
+        ((2.0 * self.mean * other.mean + C1) * (2.0 * covariance + C2))
+            / ((self.mean.powi(2) + other.mean.powi(2) + C1)
+                * (self.variance + other.variance + C2))
+    }
+}
+
+lazy_static! {
+    /// Every glyph of the bundled font, with its character and unblurred bitmap
+    static ref GLYPH_PATCHES: Vec<(char, Vec<bool>, GlyphPatch)> = font::GLYPHS
+        .iter()
+        .enumerate()
+        .map(|(i, rows)| {
+            let bits: Vec<bool> = rows
+                .iter()
+                .flat_map(|row| (0..GLYPH_WIDTH).map(move |x| row & (0x80 >> x) != 0))
+                .collect();
+            let values = bits.iter().map(|b| *b as u8 as f32).collect();
+            let chr = char::from_u32(font::FIRST_CHAR as u32 + i as u32).unwrap();
+            (chr, bits, GlyphPatch::new(values))
+        })
+        .collect();
+}
+
+/// Average every value with its neighbours in a 3x3 window
+fn box_blur(values: &[f32], width: usize, height: usize) -> Vec<f32> {
+    (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let (mut sum, mut count) = (0.0, 0.0);
+            for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                    sum += values[ny * width + nx];
+                    count += 1.0;
+                }
+            }
+            sum / count
+        })
+        .collect()
+}
+
+/// Display the image as ASCII art, choosing the character whose glyph shape
+/// in the bundled bitmap font best matches the pixels of each cell
+pub(crate) fn display_stream_shape(grid: &mut CellGrid, img: &ImageBuffer<Rgba<u8>, Vec<u8>>) {
+    let (width, height) = img.dimensions();
+    for y in 0..(height / GLYPH_HEIGHT) {
+        for x in 0..(width / GLYPH_WIDTH) {
+            let pixels: Vec<Rgba<u8>> = (0..GLYPH_WIDTH * GLYPH_HEIGHT)
+                .map(|i| {
+                    *img.get_pixel(
+                        x * GLYPH_WIDTH + i % GLYPH_WIDTH,
+                        y * GLYPH_HEIGHT + i / GLYPH_WIDTH,
+                    )
+                })
+                .collect();
+            let cell = GlyphPatch::new(
+                pixels
+                    .iter()
+                    .map(|p| match is_transparent(*p) {
+                        true => 0.0,
+                        false => grayscale_value(*p) as f32 / 255.0,
+                    })
+                    .collect(),
+            );
+            let (chr, bits, _) = if cell.variance < 0.002 {
+                // Without structure to match, pick the glyph with the closest coverage
+                GLYPH_PATCHES
+                    .iter()
+                    .min_by(|a, b| {
+                        (a.2.mean - cell.mean)
+                            .abs()
+                            .total_cmp(&(b.2.mean - cell.mean).abs())
+                    })
+                    .unwrap()
+            } else {
+                GLYPH_PATCHES
+                    .iter()
+                    .max_by(|a, b| cell.similarity(&a.2).total_cmp(&cell.similarity(&b.2)))
+                    .unwrap()
+            };
+            // Color the glyph by the pixels it covers
+            let covered = pixels
+                .iter()
+                .zip(bits)
+                .filter(|(p, bit)| **bit && !is_transparent(**p))
+                .map(|(p, _)| *p)
+                .collect::<Vec<_>>();
+            if covered.is_empty() {
+                grid.add(' ', None, None);
+            } else {
+                grid.add(*chr, Some(average_color(covered.into_iter())), None);
+            }
+        }
+        grid.end_line();
+    }
+}
+
+/// Braille dot bits for each pixel of a 2x4 cell, indexed by `[y][x]`
+const BRAILLE_DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+/// Display the image in high resolution using braille patterns, one dot per pixel.
+/// The lit dots of each cell are colored with their average color.
+pub(crate) fn display_stream_braille(
+    grid: &mut CellGrid,
+    img: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    threshold: Threshold,
+) {
+    let (width, height) = img.dimensions();
+    for y in 0..(height / 4) {
+        for x in 0..(width / 2) {
+            let pixels: Vec<(u32, Rgba<u8>)> = BRAILLE_DOTS
+                .iter()
+                .enumerate()
+                .flat_map(|(dy, row)| {
+                    row.iter().enumerate().map(move |(dx, bit)| {
+                        (*bit, *img.get_pixel(x * 2 + dx as u32, y * 4 + dy as u32))
+                    })
+                })
+                .filter(|(_, pixel)| !is_transparent(*pixel))
+                .collect();
+            let values: Vec<u8> = pixels.iter().map(|(_, p)| grayscale_value(*p)).collect();
+            let cutoff = processing::threshold_value(&values, threshold);
+            let lit: Vec<&(u32, Rgba<u8>)> = pixels
+                .iter()
+                .filter(|(_, p)| grayscale_value(*p) > cutoff)
+                .collect();
+            if lit.is_empty() {
+                grid.add(' ', None, None);
+                continue;
+            }
+            let bits = lit.iter().fold(0, |bits, (bit, _)| bits | bit);
+            let chr = char::from_u32(0x2800 + bits).unwrap();
+            grid.add(chr, Some(average_color(lit.iter().map(|(_, p)| *p))), None);
+        }
+        grid.end_line();
+    }
+}
+
+/// The average color of the given pixels
+fn average_color(pixels: impl Iterator<Item = Rgba<u8>>) -> Rgb<u8> {
+    let (sum, count) = pixels.fold(([0u32; 3], 0u32), |(sum, count), p| {
+        let Rgb([r, g, b]) = rgba_to_rgb(p);
+        (
+            [sum[0] + r as u32, sum[1] + g as u32, sum[2] + b as u32],
+            count + 1,
+        )
+    });
+    let count = count.max(1);
+    Rgb([
+        (sum[0] / count) as u8,
+        (sum[1] / count) as u8,
+        (sum[2] / count) as u8,
+    ])
+}
+
+/// Maximum number of palette registers most sixel terminals support
+const SIXEL_PALETTE_SIZE: usize = 256;
+
+/// Display the image with real pixels using DEC Sixel graphics.
+/// Colors are quantized to a palette and each six-pixel-high band is run-length encoded.
+fn display_stream_sixel(
+    out: &mut dyn std::io::Write,
+    img: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+) -> std::io::Result<()> {
+    let (width, height) = img.dimensions();
+    let (palette, indices) = sixel_palette(img);
+    // P2 = 1 leaves unpainted (transparent) pixels showing the terminal background
+    write!(out, "\x1bP0;1;0q\"1;1;{};{}", width, height)?;
+    if !palette.is_empty() {
+        for (i, color) in palette.iter().enumerate() {
+            let percent = |c: u8| (c as u32 * 100 + 127) / 255;
+            write!(
+                out,
+                "#{};2;{};{};{}",
+                i,
+                percent(color[0]),
+                percent(color[1]),
+                percent(color[2])
+            )?;
+        }
+        for band in 0..height.div_ceil(6) {
+            let rows = (band * 6)..((band * 6 + 6).min(height));
+            let mut used = vec![false; palette.len()];
+            for y in rows.clone() {
+                for x in 0..width {
+                    if let Some(i) = indices[(y * width + x) as usize] {
+                        used[i] = true;
+                    }
+                }
+            }
+            let mut first = true;
+            for color in (0..palette.len()).filter(|&i| used[i]) {
+                if !first {
+                    // Return to the start of the band to overlay the next color
+                    write!(out, "$")?;
+                }
+                first = false;
+                write!(out, "#{}", color)?;
+                let sixels = (0..width).map(|x| {
+                    let bits = rows.clone().fold(0u8, |bits, y| {
+                        if indices[(y * width + x) as usize] == Some(color) {
+                            bits | 1 << (y - band * 6)
+                        } else {
+                            bits
+                        }
+                    });
+                    (b'?' + bits) as char
+                });
+                write_sixel_runs(out, sixels)?;
+            }
+            write!(out, "-")?;
         }
-        // writeln!(out)?;
-        writeln!(out, "{}", renderer.build())?;
-        renderer.clear();
+    }
+    write!(out, "\x1b\\")?;
+    writeln!(out)
+}
+
+/// Build a palette of at most `SIXEL_PALETTE_SIZE` colors and the palette index of every pixel.
+/// Images with few colors keep them exactly, others are quantized with NeuQuant.
+fn sixel_palette(img: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> (Vec<Rgb<u8>>, Vec<Option<usize>>) {
+    let mut exact: HashMap<Rgb<u8>, usize> = HashMap::new();
+    for pixel in img.pixels().filter(|p| !is_transparent(**p)) {
+        let color = rgba_to_rgb(*pixel);
+        let next = exact.len();
+        exact.entry(color).or_insert(next);
+        if exact.len() > SIXEL_PALETTE_SIZE {
+            break;
+        }
+    }
+    if exact.len() <= SIXEL_PALETTE_SIZE {
+        let mut palette = vec![Rgb([0, 0, 0]); exact.len()];
+        for (color, i) in &exact {
+            palette[*i] = *color;
+        }
+        let indices = img
+            .pixels()
+            .map(|p| (!is_transparent(*p)).then(|| exact[&rgba_to_rgb(*p)]))
+            .collect();
+        return (palette, indices);
+    }
+    let opaque: Vec<u8> = img
+        .pixels()
+        .filter(|p| !is_transparent(**p))
+        .flat_map(|p| {
+            let Rgb([r, g, b]) = rgba_to_rgb(*p);
+            [r, g, b, 255]
+        })
+        .collect();
+    let quant = color_quant::NeuQuant::new(10, SIXEL_PALETTE_SIZE, &opaque);
+    let palette = quant
+        .color_map_rgb()
+        .chunks(3)
+        .map(|c| Rgb([c[0], c[1], c[2]]))
+        .collect();
+    let indices = img
+        .pixels()
+        .map(|p| {
+            (!is_transparent(*p)).then(|| {
+                let Rgb([r, g, b]) = rgba_to_rgb(*p);
+                quant.index_of(&[r, g, b, 255])
+            })
+        })
+        .collect();
+    (palette, indices)
+}
+
+/// Write sixel characters using the `!<count><char>` repeat introducer for runs
+fn write_sixel_runs(
+    out: &mut dyn std::io::Write,
+    sixels: impl Iterator<Item = char>,
+) -> std::io::Result<()> {
+    let write_run = |out: &mut dyn std::io::Write, chr: char, count: usize| {
+        if count > 3 {
+            write!(out, "!{}{}", count, chr)
+        } else {
+            write!(out, "{}", chr.to_string().repeat(count))
+        }
+    };
+    let mut run: Option<(char, usize)> = None;
+    for chr in sixels {
+        run = match run {
+            Some((prev, count)) if prev == chr => Some((prev, count + 1)),
+            Some((prev, count)) => {
+                write_run(out, prev, count)?;
+                Some((chr, 1))
+            }
+            None => Some((chr, 1)),
+        };
+    }
+    if let Some((chr, count)) = run {
+        write_run(out, chr, count)?;
     }
     Ok(())
 }
+
+/// Maximum size of a single base64 chunk in a kitty graphics escape sequence
+const KITTY_CHUNK_SIZE: usize = 4096;
+
+/// Display the image with real pixels using the kitty graphics protocol.
+/// The raw RGBA data is base64 encoded and transmitted in chunks, then scaled by the
+/// terminal to fill the placement's cells.
+fn display_stream_kitty(
+    out: &mut dyn std::io::Write,
+    img: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    placement: Placement,
+) -> std::io::Result<()> {
+    let (width, height) = img.dimensions();
+    let payload = base64::engine::general_purpose::STANDARD.encode(img.as_raw());
+    let mut chunks = payload.as_bytes().chunks(KITTY_CHUNK_SIZE).peekable();
+    let mut first = true;
+    // An empty image still needs one (empty) chunk to be transmitted
+    while first || chunks.peek().is_some() {
+        let chunk = chunks.next().unwrap_or_default();
+        let more = chunks.peek().is_some() as u8;
+        if first {
+            // a=T transmits and displays, f=32 is RGBA and q=2 suppresses terminal responses
+            let id = placement
+                .image_id
+                .map(|id| format!("i={},p=1,", id))
+                .unwrap_or_default();
+            write!(
+                out,
+                "\x1b_Ga=T,f=32,q=2,{}s={},v={},c={},r={},z={},m={};",
+                id, width, height, placement.columns, placement.rows, placement.z_index, more
+            )?;
+        } else {
+            write!(out, "\x1b_Gm={};", more)?;
+        }
+        out.write_all(chunk)?;
+        write!(out, "\x1b\\")?;
+        first = false;
+    }
+    writeln!(out)
+}
+
+/// Delete the placements of the kitty image with `id`, whose data is replaced by the next transmission
+pub fn delete_kitty_image(out: &mut dyn std::io::Write, id: u32) -> std::io::Result<()> {
+    write!(out, "\x1b_Ga=d,d=i,i={},q=2\x1b\\", id)
+}
+
+/// Display the image with real pixels using the iTerm2 inline image protocol (`OSC 1337`).
+/// The processed image is re-encoded as PNG and fit inside the placement's cells.
+fn display_stream_iterm(
+    out: &mut dyn std::io::Write,
+    img: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    placement: Placement,
+) -> std::io::Result<()> {
+    let mut png = std::io::Cursor::new(Vec::new());
+    img.write_to(&mut png, image::ImageOutputFormat::Png)
+        .map_err(std::io::Error::other)?;
+    let png = png.into_inner();
+    write!(
+        out,
+        "\x1b]1337;File=inline=1;size={};width={};height={};preserveAspectRatio=1:{}\x07",
+        png.len(),
+        placement.columns,
+        placement.rows,
+        base64::engine::general_purpose::STANDARD.encode(&png)
+    )?;
+    writeln!(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sixel_snapshot() {
+        let red = Rgba([255, 0, 0, 255]);
+        let blue = Rgba([0, 0, 255, 255]);
+        let mut img = ImageBuffer::from_pixel(5, 7, red);
+        img.put_pixel(0, 0, Rgba([0, 0, 0, 0]));
+        for x in 0..4 {
+            img.put_pixel(x, 6, blue);
+        }
+        let mut out = Vec::new();
+        display_stream_sixel(&mut out, &img).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            concat!(
+                // Raster attributes of 5x7 pixels and the palette
+                "\x1bP0;1;0q\"1;1;5;7#0;2;100;0;0#1;2;0;0;100",
+                // The transparent pixel is left out of the first band
+                "#0}!4~-",
+                // Colors of a band are overlaid after returning to its start
+                "#0!4?@$#1!4@?-",
+                "\x1b\\\n"
+            )
+        );
+    }
+}
@@ -0,0 +1,144 @@
+// ======================== Sizing ========================
+
+use std::fmt::Display;
+
+/// Width of a character cell in screen pixels, assumed when sizing graphics
+pub const CELL_WIDTH: f32 = 8.0;
+
+/// How the image is fitted into the available cells
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fit {
+    /// Scale to fit inside the cells, preserving aspect ratio
+    Contain,
+    /// Scale to fill the cells, preserving aspect ratio and cropping the overflow
+    Cover,
+    /// Scale to exactly the cells, ignoring aspect ratio
+    Stretch,
+    /// Keep the natural size given by the scale
+    None,
+}
+
+impl Display for Fit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fit::Contain => write!(f, "contain"),
+            Fit::Cover => write!(f, "cover"),
+            Fit::Stretch => write!(f, "stretch"),
+            Fit::None => write!(f, "none"),
+        }
+    }
+}
+
+/// The character cells an image is displayed in, and the part of the image they show
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Layout {
+    pub columns: u32,
+    pub rows: u32,
+    /// Visible region of the source image as `(x, y, width, height)`
+    pub crop: (u32, u32, u32, u32),
+}
+
+/// Requested size of the displayed image
+#[derive(Debug, Clone, Copy)]
+pub struct SizeRequest {
+    /// Cells per source pixel horizontally and vertically at the natural size
+    pub natural: (f32, f32),
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fit: Fit,
+    /// Cells available in the terminal, used when neither width nor height are given
+    pub terminal: Option<(u32, u32)>,
+}
+
+/// Compute the cells an image of `dimensions` pixels is displayed in
+pub fn layout(dimensions: (u32, u32), request: SizeRequest) -> Layout {
+    let (width, height) = (dimensions.0 as f32, dimensions.1 as f32);
+    let (natural_x, natural_y) = request.natural;
+    // Rows per column of an unstretched image
+    let aspect = natural_y / natural_x;
+    let full = (0, 0, dimensions.0, dimensions.1);
+    let cells = |columns: f32, rows: f32, crop| Layout {
+        columns: columns.round().max(1.0) as u32,
+        rows: rows.round().max(1.0) as u32,
+        crop,
+    };
+    if request.fit == Fit::None {
+        return cells(width * natural_x, height * natural_y, full);
+    }
+    // The terminal only limits the size, while explicit sizes may also enlarge the image
+    let (columns, rows, enlarge) = match (request.width, request.height) {
+        (Some(columns), Some(rows)) => (columns as f32, rows as f32, true),
+        (Some(columns), None) => {
+            let columns = columns as f32;
+            return cells(columns, columns / width * height * aspect, full);
+        }
+        (None, Some(rows)) => {
+            let rows = rows as f32;
+            return cells(rows / (height * aspect) * width, rows, full);
+        }
+        (None, None) => match request.terminal {
+            Some((columns, rows)) => (columns as f32, rows as f32, false),
+            None => return cells(width * natural_x, height * natural_y, full),
+        },
+    };
+    let scale_x = columns / width;
+    let scale_y = rows / (height * aspect);
+    match request.fit {
+        Fit::Contain => {
+            let mut scale = scale_x.min(scale_y);
+            if !enlarge {
+                scale = scale.min(natural_x);
+            }
+            cells(width * scale, height * aspect * scale, full)
+        }
+        Fit::Cover => {
+            let scale = scale_x.max(scale_y);
+            // At least a pixel, unless the image has none
+            let visible_width = (columns / scale).round().max(1.0).min(width) as u32;
+            let visible_height = (rows / (aspect * scale)).round().max(1.0).min(height) as u32;
+            let crop = (
+                (dimensions.0 - visible_width) / 2,
+                (dimensions.1 - visible_height) / 2,
+                visible_width,
+                visible_height,
+            );
+            cells(columns, rows, crop)
+        }
+        Fit::Stretch | Fit::None => cells(columns, rows, full),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(fit: Fit) -> SizeRequest {
+        SizeRequest {
+            natural: (1.0, 0.5),
+            width: None,
+            height: None,
+            fit,
+            terminal: Some((80, 24)),
+        }
+    }
+
+    #[test]
+    fn cover_crop() {
+        // A square image fills 80 by 24 cells, cropped to the middle rows
+        let layout = layout((100, 100), request(Fit::Cover));
+        assert_eq!(layout.columns, 80);
+        assert_eq!(layout.rows, 24);
+        assert_eq!(layout.crop, (0, 20, 100, 60));
+    }
+
+    #[test]
+    fn empty_images() {
+        for fit in [Fit::Contain, Fit::Cover, Fit::Stretch, Fit::None] {
+            for dimensions in [(0, 0), (0, 10), (10, 0)] {
+                let layout = layout(dimensions, request(fit));
+                assert!(layout.columns >= 1 && layout.rows >= 1);
+                assert!(layout.crop.2 <= dimensions.0 && layout.crop.3 <= dimensions.1);
+            }
+        }
+    }
+}
@@ -0,0 +1,335 @@
+// ======================== Viewer ========================
+
+use std::{borrow::Cow, io::Write};
+
+use image::{Rgb, RgbaImage};
+
+use crate::{
+    filter::{self, Filter, Pipeline},
+    frame::CellGrid,
+    processing,
+    renderer::{self, Renderer},
+    rendering,
+    sizing::{self, Fit, Layout},
+    ColorMode, Dither, Protocol, Resample, Result, ShadeMethod, FONT_ASPECT_RATIO,
+};
+
+/// Draws an image in a terminal, configured with builder methods.
+///
+/// Without a size the image is drawn at one cell per pixel, times the scale.
+/// Filters are applied to the whole image in the order of their builder methods,
+/// before it is cropped and resized.
+#[derive(Clone)]
+pub struct Viewer<'a> {
+    /// The image, after the filters
+    img: Cow<'a, RgbaImage>,
+    shading: ShadeMethod,
+    /// Draws text in place of the shade method
+    renderer: Option<&'a dyn Renderer>,
+    protocol: Protocol,
+    colors: ColorMode,
+    dither: Dither,
+    resample: Resample,
+    fit: Fit,
+    width: Option<u32>,
+    height: Option<u32>,
+    terminal: Option<(u32, u32)>,
+    scale: f32,
+    /// Size of a character cell in pixels
+    cell_size: (f32, f32),
+    /// Width / height of a character cell
+    aspect_ratio: f32,
+    /// Part of the image that is drawn as `(x, y, width, height)`
+    region: Option<(u32, u32, u32, u32)>,
+    z_index: i32,
+    /// Cells of graphics placements, which the terminal scales the image to fill
+    placement: Option<(u32, u32)>,
+    /// Id of kitty images, so that frames can replace each other
+    image_id: Option<u32>,
+}
+
+impl<'a> Viewer<'a> {
+    pub fn new(img: &'a RgbaImage) -> Self {
+        Self {
+            img: Cow::Borrowed(img),
+            shading: ShadeMethod::Blocks,
+            renderer: None,
+            protocol: Protocol::Text,
+            colors: ColorMode::TrueColor,
+            dither: Dither::None,
+            resample: Resample::Area,
+            fit: Fit::Contain,
+            width: None,
+            height: None,
+            terminal: None,
+            scale: 1.0,
+            cell_size: (sizing::CELL_WIDTH, sizing::CELL_WIDTH / FONT_ASPECT_RATIO),
+            aspect_ratio: FONT_ASPECT_RATIO,
+            region: None,
+            z_index: 0,
+            placement: None,
+            image_id: None,
+        }
+    }
+
+    pub fn shade(mut self, shading: ShadeMethod) -> Self {
+        self.shading = shading;
+        self
+    }
+
+    /// Draw text with a renderer of your own instead of the shade method
+    pub fn renderer(mut self, renderer: &'a dyn Renderer) -> Self {
+        self.renderer = Some(renderer);
+        self
+    }
+
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    pub fn colors(mut self, colors: ColorMode) -> Self {
+        self.colors = colors;
+        self
+    }
+
+    pub fn dither(mut self, dither: Dither) -> Self {
+        self.dither = dither;
+        self
+    }
+
+    pub fn resample(mut self, resample: Resample) -> Self {
+        self.resample = resample;
+        self
+    }
+
+    pub fn fit(mut self, fit: Fit) -> Self {
+        self.fit = fit;
+        self
+    }
+
+    /// Draw the image in `columns` by `rows` cells, as decided by the fit
+    pub fn size(mut self, columns: u32, rows: u32) -> Self {
+        self.width = Some(columns);
+        self.height = Some(rows);
+        self
+    }
+
+    /// Draw the image `columns` cells wide, preserving its aspect ratio
+    pub fn width(mut self, columns: u32) -> Self {
+        self.width = Some(columns);
+        self
+    }
+
+    /// Draw the image `rows` cells high, preserving its aspect ratio
+    pub fn height(mut self, rows: u32) -> Self {
+        self.height = Some(rows);
+        self
+    }
+
+    /// Cells available in the terminal, which shrink the image when no size is given
+    pub fn terminal(mut self, columns: u32, rows: u32) -> Self {
+        self.terminal = Some((columns, rows));
+        self
+    }
+
+    /// Cells per pixel of the image when no size is given
+    pub fn scale(mut self, scale: f32) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    /// Size of a character cell in pixels, as given by [`crate::detection::cell_size`]
+    pub fn cell_size(mut self, width: f32, height: f32) -> Self {
+        self.cell_size = (width, height);
+        self.aspect_ratio = width / height;
+        self
+    }
+
+    /// Override the width / height of a character cell used for text
+    pub fn aspect_ratio(mut self, aspect_ratio: f32) -> Self {
+        self.aspect_ratio = aspect_ratio;
+        self
+    }
+
+    /// Draw only the region of the image at `(x, y)` of `width` by `height` pixels
+    pub fn region(mut self, x: u32, y: u32, width: u32, height: u32) -> Self {
+        self.region = Some((x, y, width, height));
+        self
+    }
+
+    /// Stacking order of graphics placements
+    pub fn z_index(mut self, z_index: i32) -> Self {
+        self.z_index = z_index;
+        self
+    }
+
+    /// Place kitty and iTerm2 images in `columns` by `rows` cells, scaled by the terminal
+    /// from the pixels of the size the image would otherwise be drawn at
+    pub fn placement(mut self, columns: u32, rows: u32) -> Self {
+        self.placement = Some((columns, rows));
+        self
+    }
+
+    /// Transmit kitty images with this id, see [`rendering::delete_kitty_image`]
+    pub fn image_id(mut self, id: u32) -> Self {
+        self.image_id = Some(id);
+        self
+    }
+
+    /// Apply a filter to the whole image, after the filters before it
+    pub fn filter(mut self, filter: &dyn Filter) -> Self {
+        filter.apply(self.img.to_mut());
+        self
+    }
+
+    /// Apply the filters of a pipeline in order
+    pub fn pipeline(self, pipeline: &Pipeline) -> Self {
+        if pipeline.is_empty() {
+            return self;
+        }
+        self.filter(pipeline)
+    }
+
+    pub fn grayscale(self, grayscale: bool) -> Self {
+        if !grayscale {
+            return self;
+        }
+        self.filter(&filter::Grayscale)
+    }
+
+    pub fn invert(self, invert: bool) -> Self {
+        if !invert {
+            return self;
+        }
+        self.filter(&filter::Invert)
+    }
+
+    /// Make pixels within `tolerance` of `color` transparent
+    pub fn remove_color(self, color: Rgb<u8>, tolerance: f32) -> Self {
+        self.filter(&filter::RemoveColor { color, tolerance })
+    }
+
+    /// Add a value to every channel
+    pub fn brightness(self, brightness: i32) -> Self {
+        self.filter(&filter::Brightness(brightness))
+    }
+
+    pub fn contrast(self, contrast: f32) -> Self {
+        self.filter(&filter::Contrast(contrast))
+    }
+
+    pub fn hue_rotation(self, degrees: i32) -> Self {
+        self.filter(&filter::HueRotate(degrees))
+    }
+
+    /// The cells the image is drawn in
+    pub fn layout(&self) -> Layout {
+        let layout = self.fitted_layout();
+        match self.placement {
+            Some((columns, rows)) if matches!(self.protocol, Protocol::Kitty | Protocol::Iterm) => {
+                Layout {
+                    columns,
+                    rows,
+                    ..layout
+                }
+            }
+            _ => layout,
+        }
+    }
+
+    /// The cells the image is fitted to and resized for, before the terminal scales it to its placement
+    fn fitted_layout(&self) -> Layout {
+        let (x, y, width, height) =
+            self.region
+                .unwrap_or((0, 0, self.img.width(), self.img.height()));
+        // Graphics protocols draw square pixels, so only character cells need aspect correction
+        let natural = if self.protocol.is_graphics() {
+            (self.scale / self.cell_size.0, self.scale / self.cell_size.1)
+        } else {
+            (self.scale, self.scale * self.aspect_ratio)
+        };
+        let layout = sizing::layout(
+            (width, height),
+            sizing::SizeRequest {
+                natural,
+                width: self.width,
+                height: self.height,
+                fit: self.fit,
+                terminal: self.terminal,
+            },
+        );
+        let crop = layout.crop;
+        Layout {
+            crop: (x + crop.0, y + crop.1, crop.2, crop.3),
+            ..layout
+        }
+    }
+
+    /// Crop and resize the image to the pixels of its cells
+    pub fn process(&self) -> Result<RgbaImage> {
+        let layout = self.fitted_layout();
+        // The pixels drawn in those cells
+        let (pixel_width, pixel_height) = if self.protocol.is_graphics() {
+            self.cell_size
+        } else {
+            let (x, y) = self.with_renderer(|renderer| Ok(renderer.resolution()))?;
+            (x as f32, y as f32)
+        };
+        let pixels = (
+            (layout.columns as f32 * pixel_width).round() as u32,
+            (layout.rows as f32 * pixel_height).round() as u32,
+        );
+        let (x, y, width, height) = layout.crop;
+        let mut img = image::imageops::crop_imm(self.img.as_ref(), x, y, width, height).to_image();
+        if pixels != img.dimensions() {
+            // Sub-cell pixels are resampled directly, so each half block or dot averages its own area
+            img = processing::resize(&img, pixels.0, pixels.1, self.resample);
+        }
+        Ok(img)
+    }
+
+    /// Render the image as text into a grid of cells, whatever the protocol
+    pub fn render_cells(&self) -> Result<CellGrid> {
+        let img = self.process()?;
+        self.with_renderer(|renderer| renderer.render_cells(&img))
+    }
+
+    /// Write the image to `out` with the protocol, ending on the line below it
+    pub fn render_to(&self, out: &mut impl Write) -> Result<()> {
+        let img = self.process()?;
+        if !self.protocol.is_graphics() {
+            return self.with_renderer(|renderer| renderer.render(&img, out));
+        }
+        let layout = self.layout();
+        rendering::display(
+            out,
+            &img,
+            self.shading.clone(),
+            self.protocol,
+            rendering::Placement {
+                columns: layout.columns,
+                rows: layout.rows,
+                z_index: self.z_index,
+                image_id: self.image_id,
+            },
+            self.colors,
+            self.dither,
+        )
+    }
+
+    /// Call `f` with the renderer of text, which is that of the shade method unless one is given
+    fn with_renderer<T>(&self, f: impl FnOnce(&dyn Renderer) -> Result<T>) -> Result<T> {
+        match self.renderer {
+            Some(renderer) => f(renderer),
+            None => {
+                let options = renderer::Options {
+                    colors: self.colors,
+                    dither: self.dither,
+                    ..renderer::Options::default()
+                };
+                f(self.shading.renderer(options)?.as_ref())
+            }
+        }
+    }
+}